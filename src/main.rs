@@ -1,21 +1,134 @@
-use ansi_term;
 // use anyhow::{anyhow, Result};
 use clap::Parser;
 // use libc::{setlocale, LC_ALL};
 // use std::ffi::CString;
-use std::{env, path::Path};
+use notify::{RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, fs, thread};
 use tracing::{error, info, trace, warn};
-use tracing_subscriber;
+use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*};
 mod packager_command;
 
-#[derive(clap::Parser, Debug)]
+#[derive(clap::Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     // 配置文件路径
-    #[arg(short, long)]
-    config: String,
+    #[arg(short, long, required_unless_present_any = ["init", "emit_schema", "list_commands"])]
+    config: Option<String>,
     #[arg(short, long)]
     workdir: Option<String>,
+    // 解析命令中的相对路径和配置文件中import的相对路径时使用的根目录，默认为配置文件所在目录
+    #[arg(long)]
+    base_dir: Option<String>,
+    // 选择配置文件中的环境，应用profiles中的定义项和命令筛选，以及overrides中的修改
+    #[arg(long)]
+    env: Option<String>,
+    // 只执行指定阶段的命令
+    #[arg(short, long)]
+    stage: Option<String>,
+    // 指定阶段时，同时执行未分阶段的命令
+    #[arg(long, requires = "stage")]
+    only_stage: bool,
+    // 输出目录，会拼接到所有相对的目标路径之前
+    #[arg(short, long)]
+    output_dir: Option<String>,
+    // 失败的命令数量达到该值时停止执行，默认执行全部命令
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_failures: Option<u64>,
+    // 遇到第一个错误时立即停止，相当于--max-failures 1，并且copy命令中有文件复制失败时也立即中止
+    #[arg(long, conflicts_with = "max_failures")]
+    fail_fast: bool,
+    // 按阶段、run_if和--continue-from筛选之后一条命令都没有执行时，以非零状态码退出
+    #[arg(long)]
+    fail_if_empty: bool,
+    // 以yaml格式输出变量替换后最终要执行的配置，然后退出而不执行命令
+    #[arg(long)]
+    print_config: bool,
+    // 将变量替换后的配置以yaml格式写入指定文件，标记为secret的变量的值替换为***，然后继续执行
+    #[arg(long)]
+    export_config: Option<String>,
+    // 逐条输出最终要执行的命令的说明，然后退出而不执行命令
+    #[arg(long)]
+    explain: bool,
+    // 输出最终的执行计划，包括阶段、order以及并行组中一起执行的命令，然后退出而不执行命令
+    #[arg(long)]
+    dump_plan: bool,
+    // 执行计划的格式
+    #[arg(long, value_enum, default_value_t = PlanFormat::Text, requires = "dump_plan")]
+    plan_format: PlanFormat,
+    // 在执行之前检查命令（目前检查run命令的可执行文件能否找到），然后退出而不执行命令
+    #[arg(long)]
+    check: bool,
+    // 要求所有目标路径都是相对路径并且不含..，否则在执行之前报错，用于执行不完全可信的配置
+    #[arg(long)]
+    strict_paths: bool,
+    // 在指定路径生成一个示例配置文件，默认为config.yml
+    #[arg(long, num_args = 0..=1, default_missing_value = "config.yml")]
+    init: Option<String>,
+    // 输出描述配置文件格式的JSON Schema，然后退出，可用于编辑器的自动补全和校验
+    #[arg(long)]
+    emit_schema: bool,
+    // 列出所有可用的命令类型及其字段、类型和是否可选，然后退出
+    #[arg(long)]
+    list_commands: bool,
+    // 生成示例配置文件时覆盖已存在的文件
+    #[arg(long, requires = "init")]
+    force: bool,
+    // 对所有copy命令启用增量模式，跳过未改变的文件
+    #[arg(long)]
+    incremental: bool,
+    // 统计copy和replace命令内部的耗时、字节数等数据，并在结束时输出
+    #[arg(long)]
+    profile: bool,
+    // 执行完成后将输出目录与该参考目录比较，列出新增、删除和修改的文件
+    #[arg(long, requires = "output_dir")]
+    diff_against: Option<String>,
+    // 将比较结果以json格式写入该文件
+    #[arg(long, requires = "diff_against")]
+    diff_json: Option<String>,
+    // 执行完成后将输出目录中所有文件的路径、大小和sha256以json格式写入该文件
+    #[arg(long)]
+    sbom: Option<String>,
+    // 生成物料清单的目录，可以指定多次，默认为输出目录
+    #[arg(long, requires = "sbom")]
+    sbom_dir: Vec<String>,
+    // 控制台输出的日志级别
+    #[arg(long, default_value = "trace")]
+    log_level: tracing::Level,
+    // 同时将trace级别的完整日志写入该文件，每次运行时覆盖
+    #[arg(long)]
+    log_file: Option<String>,
+    // 执行所有命令的总时间上限（秒），超时后中止正在执行的命令并跳过剩余的命令
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+    // 执行完成后监视配置文件和各命令的源路径，有改变时重新执行，直到按下Ctrl-C
+    #[arg(long)]
+    watch: bool,
+    // 预演模式，replace只输出将要进行的改动而不写入文件，其他命令跳过不执行
+    #[arg(long)]
+    dry_run: bool,
+    // 跳过前N条命令，从序号为N的命令（从0开始）继续执行，需要自行保证之前命令的结果仍然存在
+    #[arg(long)]
+    continue_from: Option<usize>,
+    // 日志中最多输出的run和custom命令的stdout和stderr字节数，超出的部分被截断
+    #[arg(long)]
+    max_output_bytes: Option<usize>,
+    // copy命令的源路径不存在时只输出警告并跳过，相当于对所有copy设置allow_missing_source
+    #[arg(long)]
+    no_op_on_missing_source: bool,
+    // 对所有没有设置min_free_bytes的copy命令，复制之前检查目标路径所在文件系统至少有这么多可用空间
+    #[arg(long)]
+    min_free_bytes: Option<u64>,
+    // replace命令并行处理文件时使用的线程数，默认为CPU核心数
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+    jobs: Option<u64>,
+    // 分别以两个临时目录作为输出目录执行两遍，比较两次的输出是否完全相同，用于检查构建是否可重现
+    #[arg(long, conflicts_with_all = ["output_dir", "watch", "dry_run"])]
+    verify_idempotent: bool,
 }
 
 fn main() {
@@ -27,8 +140,38 @@ fn main() {
         });
     }
 
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::TRACE)
+    let args = Args::parse();
+
+    // 日志输出到stderr，stdout只用于输出--print-config等结果
+    // 命令设置了log_level时，执行该命令期间改用命令的级别
+    let console_layer =
+        fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_filter(packager_command::CommandLogFilter(LevelFilter::from_level(
+                args.log_level,
+            )));
+    // 指定日志文件时，另外以trace级别写入完整日志，写入在后台线程中进行
+    // log_guard被drop时会写完剩余的日志，因此需要保留到程序结束
+    let (file_layer, log_guard) = match &args.log_file {
+        Some(log_file) => match fs::File::create(log_file) {
+            Ok(file) => {
+                let (writer, guard) = tracing_appender::non_blocking(file);
+                let layer = fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(writer)
+                    .with_filter(LevelFilter::TRACE);
+                (Some(layer), Some(guard))
+            }
+            Err(e) => {
+                eprintln!("Failed to create log file {}: {}", log_file, e);
+                return;
+            }
+        },
+        None => (None, None),
+    };
+    tracing_subscriber::registry()
+        .with(console_layer)
+        .with(file_layer)
         .init();
 
     // let locale = CString::new("zh_CN.UTF-8").unwrap();
@@ -36,27 +179,339 @@ fn main() {
     //     setlocale(LC_ALL, locale.as_ptr());
     // }
 
-    let args = Args::parse();
+    if let Some(init_path) = &args.init {
+        init_config(init_path, args.force);
+        return;
+    }
+
+    if args.emit_schema {
+        match packager_command::config_schema_json() {
+            Ok(schema) => println!("{}", schema),
+            Err(e) => error!("Failed to generate schema: {}", e),
+        }
+        return;
+    }
+
+    if args.list_commands {
+        match packager_command::list_commands_text() {
+            Ok(text) => print!("{}", text),
+            Err(e) => error!("Failed to list commands: {}", e),
+        }
+        return;
+    }
+
+    let config_dir: &str = args
+        .config
+        .as_deref()
+        .expect("--config is required unless --init, --emit-schema or --list-commands is given");
+
+    // 收到Ctrl-C或SIGTERM时，请求停止执行，当前命令结束或中止后跳过剩余的命令
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let handler_cancelled = cancelled.clone();
+    if let Err(e) = ctrlc::set_handler(move || {
+        warn!("Interrupt received, stopping...");
+        handler_cancelled.store(true, Ordering::SeqCst);
+    }) {
+        warn!("Failed to set signal handler: {}", e);
+    }
+
+    // 监视模式下每次执行前都回到启动时的路径，使参数中相对路径的含义保持不变
+    let launch_dir = env::current_dir();
+    if args.verify_idempotent {
+        let reproducible = match launch_dir {
+            Ok(launch_dir) => verify_idempotent(&args, config_dir, &launch_dir, &cancelled),
+            Err(e) => {
+                error!("Failed to get current directory: {}", e);
+                false
+            }
+        };
+        // 输出不同或没有完成比较时以非零状态码退出，使CI能够发现
+        if !reproducible {
+            // process::exit不会执行析构，需要先写完日志文件
+            drop(log_guard);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let mut outcome = package(&args, config_dir, &cancelled);
+    if args.watch {
+        match (launch_dir, std::path::absolute(config_dir)) {
+            (Ok(launch_dir), Ok(config_path)) => {
+                while let Outcome::Finished(watch_list) = &outcome {
+                    if !wait_for_changes(&config_path, watch_list, &cancelled) {
+                        info!("Stopped watching");
+                        break;
+                    }
+                    info!("==================== Changes detected, re-running ====================");
+                    if let Err(e) = env::set_current_dir(&launch_dir) {
+                        error!("Failed to change current directory: {}", e);
+                        break;
+                    }
+                    outcome = package(&args, config_dir, &cancelled);
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => error!("Failed to start watching: {}", e),
+        }
+    }
 
+    match outcome {
+        Outcome::Finished(_) => {}
+        Outcome::Empty => {
+            // process::exit不会执行析构，需要先写完日志文件
+            drop(log_guard);
+            std::process::exit(1);
+        }
+        Outcome::TimedOut => {
+            error!("Packaging timed out");
+            // process::exit不会执行析构，需要先写完日志文件
+            drop(log_guard);
+            std::process::exit(124);
+        }
+        Outcome::Interrupted => {
+            error!("Packaging was interrupted");
+            // process::exit不会执行析构，需要先写完日志文件
+            drop(log_guard);
+            std::process::exit(130);
+        }
+    }
+}
+
+// --dump-plan输出的格式
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum PlanFormat {
+    Text, // 逐行列出命令
+    Dot,  // Graphviz DOT
+}
+
+// 一次打包的结果
+enum Outcome {
+    // 正常结束（包括命令执行出错），附带监视模式下要监视的路径
+    Finished(WatchList),
+    // 收到Ctrl-C或SIGTERM而中止
+    Interrupted,
+    // 超过--timeout的时间上限而中止
+    TimedOut,
+    // 传入--fail-if-empty并且没有执行任何命令
+    Empty,
+}
+
+// 监视模式下要监视的路径，均为绝对路径
+#[derive(Default)]
+struct WatchList {
+    // 命令的源路径
+    sources: Vec<PathBuf>,
+    // 由打包过程本身写入的路径，例如命令的目标路径和日志文件，其中的改变不触发重新执行
+    ignored: Vec<PathBuf>,
+}
+
+impl WatchList {
+    // 判断改变的路径是否需要重新执行
+    fn is_relevant(&self, config_path: &Path, path: &Path) -> bool {
+        path == config_path
+            || (self.sources.iter().any(|source| path.starts_with(source))
+                && !self.ignored.iter().any(|ignored| path.starts_with(ignored)))
+    }
+}
+
+// 解析配置文件并执行一次打包
+fn package(args: &Args, config_dir: &str, cancelled: &Arc<AtomicBool>) -> Outcome {
     info!("starting packager...");
-    trace!("The config file path is: {}", args.config);
+    trace!("The config file path is: {}", config_dir);
+
+    // 根目录是相对于启动时的当前路径，需要在解析配置之前转换为绝对路径
+    let base_dir = match args
+        .base_dir
+        .as_deref()
+        .map(std::path::absolute)
+        .transpose()
+    {
+        Ok(base_dir) => base_dir,
+        Err(e) => {
+            error!("Invalid base directory: {}", e);
+            return Outcome::Finished(WatchList::default());
+        }
+    };
 
-    let config_dir: &str = &args.config;
-    let config = match packager_command::parse_commands_from_yaml(config_dir, true) {
-        Ok(config) => {
+    let (mut config, variables) = match packager_command::parse_commands_from_yaml(
+        config_dir,
+        true,
+        base_dir.as_deref(),
+        args.env.as_deref(),
+    ) {
+        Ok(result) => {
             trace!("read file successfully");
-            config
+            result
         }
         Err(e) => {
             error!(error = ?e, "failed to read file");
-            return;
+            return Outcome::Finished(WatchList::default());
         }
     };
 
     // 打印Config对象的内容，验证反序列化是否正确
-    println!("{:#?}", config);
+    trace!("{:#?}", config);
+    // 筛选之前配置中的命令数量，用于--fail-if-empty的报告
+    let defined = config.command.len();
+
+    // 条件不成立时整个配置都不需要执行
+    if let Some(run_if) = &config.run_if {
+        match packager_command::evaluate_condition(run_if) {
+            Ok(true) => trace!("run_if is met: {}", run_if),
+            Ok(false) => {
+                info!("Skipping, run_if not met: {}", run_if);
+                if args.fail_if_empty {
+                    error!(
+                        "No commands were executed: {} command(s) defined, 0 executed",
+                        defined
+                    );
+                    return Outcome::Empty;
+                }
+                return Outcome::Finished(WatchList::default());
+            }
+            Err(e) => {
+                error!("Invalid run_if: {:#}", e);
+                return Outcome::Finished(WatchList::default());
+            }
+        }
+    }
+
+    // 按order和阶段排序，并筛选要执行的命令
+    config.command = packager_command::select_stage(
+        packager_command::sort_by_order(config.command),
+        args.stage.as_deref(),
+        args.only_stage,
+    );
+
+    // 目标路径在拼接输出目录和根目录之后都会变成绝对路径，因此在此之前检查
+    let path_errors = if args.strict_paths {
+        packager_command::check_strict_paths(&config.command)
+    } else {
+        Vec::new()
+    };
+    if !path_errors.is_empty() && !args.check {
+        error!(
+            "Refusing to run, {} destination(s) violate --strict-paths:",
+            path_errors.len()
+        );
+        for error in &path_errors {
+            error!("- {:#}", error);
+        }
+        return Outcome::Finished(WatchList::default());
+    }
+
+    // 输出目录是相对于启动时的当前路径，需要在切换工作路径之前转换为绝对路径
+    let mut absolute_output_dir = None;
+    if let Some(output_dir) = &args.output_dir {
+        match std::path::absolute(output_dir) {
+            Ok(output_dir) => {
+                trace!("The output directory is: {}", output_dir.display());
+                packager_command::apply_output_dir(&mut config.command, &output_dir);
+                absolute_output_dir = Some(output_dir);
+            }
+            Err(e) => {
+                error!("Invalid output directory {}: {}", output_dir, e);
+                return Outcome::Finished(WatchList::default());
+            }
+        }
+    }
+    // 其余的相对路径相对于根目录，转换为绝对路径后不再受工作路径的影响
+    if let Some(base_dir) = &base_dir {
+        trace!("The base directory is: {}", base_dir.display());
+        packager_command::apply_base_dir(&mut config.command, base_dir);
+    }
+    // 参考目录、比较结果和日志文件的路径同样相对于启动时的当前路径
+    let (diff_against, diff_json) = match (
+        args.diff_against
+            .as_deref()
+            .map(std::path::absolute)
+            .transpose(),
+        args.diff_json
+            .as_deref()
+            .map(std::path::absolute)
+            .transpose(),
+    ) {
+        (Ok(diff_against), Ok(diff_json)) => (diff_against, diff_json),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Invalid diff path: {}", e);
+            return Outcome::Finished(WatchList::default());
+        }
+    };
+    // 物料清单默认列出输出目录，路径同样相对于启动时的当前路径
+    let sbom_dirs = if args.sbom_dir.is_empty() {
+        args.output_dir.iter().cloned().collect::<Vec<_>>()
+    } else {
+        args.sbom_dir.clone()
+    };
+    if args.sbom.is_some() && sbom_dirs.is_empty() {
+        error!("--sbom requires --output-dir or --sbom-dir");
+        return Outcome::Finished(WatchList::default());
+    }
+    let (sbom, sbom_dirs) = match (
+        args.sbom.as_deref().map(std::path::absolute).transpose(),
+        sbom_dirs
+            .into_iter()
+            .map(|dir| std::path::absolute(&dir).map(|path| (dir, path)))
+            .collect::<Result<Vec<_>, _>>(),
+    ) {
+        (Ok(sbom), Ok(sbom_dirs)) => (sbom, sbom_dirs),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Invalid sbom path: {}", e);
+            return Outcome::Finished(WatchList::default());
+        }
+    };
+    let log_file = args
+        .log_file
+        .as_deref()
+        .and_then(|log_file| std::path::absolute(log_file).ok());
+
+    if let Some(export_config) = &args.export_config {
+        match packager_command::export_config_yaml(&config, &variables)
+            .and_then(|yaml| Ok(fs::write(export_config, yaml)?))
+        {
+            Ok(()) => info!("Exported the resolved config to {}", export_config),
+            Err(e) => error!("Failed to export config to {}: {:#}", export_config, e),
+        }
+    }
+
+    if args.print_config {
+        match serde_yaml::to_string(&config) {
+            Ok(yaml) => print!("{}", yaml),
+            Err(e) => error!("Failed to serialize config: {}", e),
+        }
+        return Outcome::Finished(WatchList::default());
+    }
 
-    match args.workdir {
+    if args.explain {
+        for (index, item) in config.command.iter().enumerate() {
+            match &item.stage {
+                Some(stage) => println!("Step #{} [{}]: {}", index, stage, item.command.describe()),
+                None => println!("Step #{}: {}", index, item.command.describe()),
+            }
+        }
+        return Outcome::Finished(WatchList::default());
+    }
+
+    if args.dump_plan {
+        match args.plan_format {
+            PlanFormat::Text => print!("{}", packager_command::plan_text(&config.command)),
+            PlanFormat::Dot => print!("{}", packager_command::plan_dot(&config.command)),
+        }
+        return Outcome::Finished(WatchList::default());
+    }
+
+    // 序号与--explain和错误信息中的一致，即按阶段筛选和排序之后的序号
+    if let Some(continue_from) = args.continue_from {
+        if continue_from >= config.command.len() {
+            error!(
+                "--continue-from {} is out of range, there are only {} command(s)",
+                continue_from,
+                config.command.len()
+            );
+            return Outcome::Finished(WatchList::default());
+        }
+    }
+
+    match &args.workdir {
         None => {
             // 如果没有传入工作路径参数，则根据配置文件路径来设置当前工作路径
             if let Some(config_parent_dir) = Path::new(config_dir).parent() {
@@ -77,7 +532,7 @@ fn main() {
             }
         }
         Some(path) => {
-            if let Err(e) = env::set_current_dir(&path) {
+            if let Err(e) = env::set_current_dir(path) {
                 error!("Failed to change current directory: {}", e);
             } else {
                 info!("Successfully changed current directory to {}", path);
@@ -85,7 +540,92 @@ fn main() {
         }
     }
 
-    match packager_command::execute_commands(&config.command) {
+    // 可执行文件的相对路径是相对于工作路径的，因此在切换工作路径之后检查
+    if args.check {
+        let mut errors = path_errors;
+        errors.extend(packager_command::check_commands(&config.command));
+        if errors.is_empty() {
+            info!("Check passed, {} command(s) found", config.command.len());
+        } else {
+            error!("Check found {} problem(s):", errors.len());
+            for error in &errors {
+                error!("- {:#}", error);
+            }
+        }
+        return Outcome::Finished(WatchList::default());
+    }
+
+    // 命令中的相对路径是相对于工作路径的，切换工作路径之后再转换为绝对路径
+    let absolute_paths = |paths: Vec<PathBuf>| -> Vec<PathBuf> {
+        paths
+            .into_iter()
+            .filter_map(|path| std::path::absolute(path).ok())
+            .collect()
+    };
+    let watch_list = WatchList {
+        sources: absolute_paths(
+            config
+                .command
+                .iter()
+                .flat_map(|item| item.command.sources())
+                .collect(),
+        ),
+        ignored: absolute_paths(
+            config
+                .command
+                .iter()
+                .flat_map(|item| item.command.destinations())
+                .map(PathBuf::from)
+                .chain(log_file)
+                .chain([PathBuf::from(packager_command::RUN_CACHE_DIR)])
+                .chain(diff_json.clone())
+                .chain(sbom.clone())
+                .collect(),
+        ),
+    };
+
+    let options = packager_command::ExecuteOptions {
+        max_failures: if args.fail_fast {
+            Some(1)
+        } else {
+            args.max_failures
+        },
+        fail_fast: args.fail_fast,
+        dry_run: args.dry_run,
+        incremental: args.incremental,
+        cancelled: cancelled.clone(),
+        profile: args.profile,
+        variables,
+        strict_env: config.strict_env,
+        global_exclude: config.global_exclude.clone().unwrap_or_default(),
+        jobs: args.jobs.map(|jobs| jobs as usize),
+        continue_from: args.continue_from.unwrap_or(0),
+        max_output_bytes: args.max_output_bytes,
+        allow_missing_source: args.no_op_on_missing_source,
+        min_free_bytes: args.min_free_bytes,
+        matrix: config.matrix.clone().unwrap_or_default(),
+        ..Default::default()
+    };
+
+    // 超时后同样通过取消标志停止执行
+    // 监视模式下会多次执行，finished用于让之前的计时线程在本次执行结束后不再生效
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let finished = Arc::new(AtomicBool::new(false));
+    if let Some(timeout) = args.timeout {
+        let cancelled = cancelled.clone();
+        let timed_out = timed_out.clone();
+        let finished = finished.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(timeout));
+            if !finished.load(Ordering::SeqCst) {
+                error!("Timed out after {} second(s), stopping...", timeout);
+                timed_out.store(true, Ordering::SeqCst);
+                cancelled.store(true, Ordering::SeqCst);
+            }
+        });
+    }
+
+    match packager_command::execute_commands(&config.command, &options) {
         Ok(_) => {
             info!("All commands executed successfully!");
         }
@@ -95,6 +635,287 @@ fn main() {
                 e.len(),
                 config.command.len()
             );
+            // 逐条列出出错的命令及原因
+            for (_, error) in &e {
+                error!("- {:#}", error);
+            }
+            // 对每条出错的命令给出重新执行的命令行：从该命令继续执行，跳过之前耗时的步骤，
+            // 命令属于某个阶段时，还可以只执行该阶段；matrix中同一条命令可能多次出错，只提示一次
+            let launch_args: Vec<String> = env::args().collect();
+            let mut failed: Vec<usize> = e.iter().map(|(index, _)| *index).collect();
+            failed.sort_unstable();
+            failed.dedup();
+            error!("After fixing the problem, re-run a failed command with:");
+            for index in failed {
+                let item = &config.command[index];
+                error!(
+                    "- command #{} ({}): {}",
+                    index,
+                    item.command.kind(),
+                    packager_command::rerun_command_line(
+                        &launch_args,
+                        packager_command::Rerun::ContinueFrom(index)
+                    )
+                );
+                if let Some(stage) = item.stage.as_deref() {
+                    if args.stage.as_deref() != Some(stage) {
+                        error!(
+                            "  or only its stage: {}",
+                            packager_command::rerun_command_line(
+                                &launch_args,
+                                packager_command::Rerun::Stage(stage)
+                            )
+                        );
+                    }
+                }
+            }
+        }
+    }
+    finished.store(true, Ordering::SeqCst);
+
+    if let (Some(output_dir), Some(reference_dir)) = (&absolute_output_dir, &diff_against) {
+        if !options.cancelled.load(Ordering::SeqCst) {
+            report_diff(output_dir, reference_dir, diff_json.as_deref());
+        }
+    }
+
+    if let Some(sbom) = &sbom {
+        if !options.cancelled.load(Ordering::SeqCst) {
+            write_sbom(sbom, &sbom_dirs);
+        }
+    }
+
+    if args.profile {
+        info!("Profile:");
+        if let Ok(report) = options.profile_report.lock() {
+            for line in report.iter() {
+                info!("- {}", line);
+            }
+        }
+    }
+
+    // 被中止时以中止的结果为准，不再检查是否执行了命令
+    let executed = options.executed.load(Ordering::SeqCst);
+    let empty = args.fail_if_empty && executed == 0;
+    if args.fail_if_empty {
+        let report = format!(
+            "{} command(s) defined, {} selected, {} executed",
+            defined,
+            config.command.len(),
+            executed
+        );
+        if empty && !options.cancelled.load(Ordering::SeqCst) {
+            error!("No commands were executed: {}", report);
+        } else {
+            info!("{}", report);
         }
     }
+
+    if timed_out.load(Ordering::SeqCst) {
+        Outcome::TimedOut
+    } else if options.cancelled.load(Ordering::SeqCst) {
+        Outcome::Interrupted
+    } else if empty {
+        Outcome::Empty
+    } else {
+        Outcome::Finished(watch_list)
+    }
+}
+
+// 监视配置文件和命令的源路径，等到有改变并且一段时间内没有新的改变时返回true，收到Ctrl-C时返回false
+fn wait_for_changes(config_path: &Path, watch_list: &WatchList, cancelled: &AtomicBool) -> bool {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = match notify::recommended_watcher(sender) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            error!("Failed to create file watcher: {}", e);
+            return false;
+        }
+    };
+    // 编辑器保存时常常会替换掉原文件，因此监视配置文件所在的目录而不是文件本身
+    let config_parent = config_path.parent().unwrap_or(config_path);
+    let watched = std::iter::once((config_parent, RecursiveMode::NonRecursive)).chain(
+        watch_list
+            .sources
+            .iter()
+            .map(|source| (source.as_path(), RecursiveMode::Recursive)),
+    );
+    for (path, mode) in watched {
+        if let Err(e) = watcher.watch(path, mode) {
+            warn!("Failed to watch {}: {}", path.display(), e);
+        }
+    }
+    info!("Watching for changes, press Ctrl-C to stop...");
+
+    // 等待第一个需要重新执行的改变，忽略只读取文件的事件
+    let is_relevant = |event: &notify::Event| {
+        !event.kind.is_access()
+            && event
+                .paths
+                .iter()
+                .any(|path| watch_list.is_relevant(config_path, path))
+    };
+    loop {
+        if cancelled.load(Ordering::SeqCst) {
+            return false;
+        }
+        match receiver.recv_timeout(Duration::from_millis(200)) {
+            Ok(Ok(event)) if is_relevant(&event) => {
+                trace!("Changed: {:?}", event.paths);
+                break;
+            }
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+    // 去抖：保存多个文件时会连续产生事件，等到安静下来再重新执行
+    while receiver.recv_timeout(Duration::from_millis(500)).is_ok() {}
+    !cancelled.load(Ordering::SeqCst)
+}
+
+// 比较输出目录与参考目录，输出差异摘要，指定json_path时同时将差异写入json文件
+fn report_diff(output_dir: &Path, reference_dir: &Path, json_path: Option<&Path>) {
+    let diff = match packager_command::diff_dirs(output_dir, reference_dir) {
+        Ok(diff) => diff,
+        Err(e) => {
+            error!(
+                "Failed to compare with {}: {:#}",
+                reference_dir.display(),
+                e
+            );
+            return;
+        }
+    };
+    info!(
+        "Compared with {}: {} added, {} removed, {} modified",
+        reference_dir.display(),
+        diff.added.len(),
+        diff.removed.len(),
+        diff.modified.len()
+    );
+    for path in &diff.added {
+        info!("+ {}", path);
+    }
+    for path in &diff.removed {
+        info!("- {}", path);
+    }
+    for path in &diff.modified {
+        info!("M {}", path);
+    }
+
+    if let Some(json_path) = json_path {
+        let result = serde_json::to_string_pretty(&diff)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(fs::write(json_path, json)?));
+        if let Err(e) = result {
+            error!("Failed to write {}: {:#}", json_path.display(), e);
+        }
+    }
+}
+
+// 将目录中所有文件的物料清单写入json文件
+fn write_sbom(path: &Path, dirs: &[(String, PathBuf)]) {
+    let result = packager_command::sbom_json(dirs).and_then(|json| Ok(fs::write(path, json)?));
+    match result {
+        Ok(()) => info!("Wrote SBOM to {}", path.display()),
+        Err(e) => error!("Failed to write SBOM {}: {:#}", path.display(), e),
+    }
+}
+
+// 分别以两个临时目录作为输出目录执行两遍，按内容比较两次的输出（不比较修改时间）
+// 输出不同时保留两个临时目录以便检查，相同时删除；返回两次的输出是否相同
+fn verify_idempotent(
+    args: &Args,
+    config_dir: &str,
+    launch_dir: &Path,
+    cancelled: &Arc<AtomicBool>,
+) -> bool {
+    let mut output_dirs = Vec::new();
+    for run in 1..=2 {
+        let output_dir = match tempfile::Builder::new()
+            .prefix(&format!("autopackager-run{}-", run))
+            .tempdir()
+        {
+            Ok(output_dir) => output_dir,
+            Err(e) => {
+                error!("Failed to create temporary directory: {}", e);
+                return false;
+            }
+        };
+        info!(
+            "==================== Run {} of 2 into {} ====================",
+            run,
+            output_dir.path().display()
+        );
+        // 每次执行前都回到启动时的路径，使参数中相对路径的含义保持不变
+        if let Err(e) = env::set_current_dir(launch_dir) {
+            error!("Failed to change current directory: {}", e);
+            return false;
+        }
+        let run_args = Args {
+            output_dir: Some(output_dir.path().display().to_string()),
+            ..args.clone()
+        };
+        if !matches!(
+            package(&run_args, config_dir, cancelled),
+            Outcome::Finished(_)
+        ) {
+            error!("Run {} did not finish, skipping the comparison", run);
+            return false;
+        }
+        output_dirs.push(output_dir);
+    }
+
+    let (first, second) = (output_dirs[0].path(), output_dirs[1].path());
+    let diff = match packager_command::diff_dirs(second, first) {
+        Ok(diff) => diff,
+        Err(e) => {
+            error!("Failed to compare the outputs: {:#}", e);
+            return false;
+        }
+    };
+    if diff.added.is_empty() && diff.removed.is_empty() && diff.modified.is_empty() {
+        info!("Both runs produced identical output, the packaging is reproducible");
+        return true;
+    }
+    error!(
+        "The two runs produced different output: {} only in the second run, {} only in the first run, {} differ",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.modified.len()
+    );
+    for path in &diff.added {
+        error!("+ {}", path);
+    }
+    for path in &diff.removed {
+        error!("- {}", path);
+    }
+    for path in &diff.modified {
+        error!("M {}", path);
+    }
+    for output_dir in output_dirs {
+        let output_dir = output_dir.keep();
+        info!("Kept {} for inspection", output_dir.display());
+    }
+    false
+}
+
+// 生成示例配置文件，目标文件已存在时除非指定force，否则不覆盖
+fn init_config(path: &str, force: bool) {
+    if Path::new(path).exists() && !force {
+        error!("{} already exists, use --force to overwrite it", path);
+        return;
+    }
+
+    let content = match packager_command::example_config_yaml() {
+        Ok(content) => content,
+        Err(e) => {
+            error!("Failed to generate example config: {}", e);
+            return;
+        }
+    };
+    match fs::write(path, content) {
+        Ok(_) => info!("Example config written to {}", path),
+        Err(e) => error!("Failed to write {}: {}", path, e),
+    }
 }
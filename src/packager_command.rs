@@ -1,64 +1,930 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use glob::glob;
-use regex::Regex;
+use regex::bytes::{Regex as BytesRegex, RegexBuilder as BytesRegexBuilder};
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
 use std::fs;
-use std::path::Path;
-use std::process::Command as SysCommand;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command as SysCommand, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use base64::Engine;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
 use ignore::WalkBuilder;
 use itertools::Itertools;
-use serde::{Deserialize, Serialize};
-use serde_yaml;
-use tracing::{error, info, trace};
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::level_filters::LevelFilter;
+use tracing::subscriber::Interest;
+use tracing::{error, info, trace, warn, Metadata};
+use tracing_subscriber::layer::{self, Filter};
 
 // 定义一个结构体，表示整个yaml对象
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug)]
 pub struct Config {
+    // 导入的其他配置文件，路径相对于当前配置文件所在目录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub import: Option<Vec<String>>,
+    // 变量文件（yaml或json），其中的键值对会作为变量加载，路径相对于配置文件所在目录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vars_file: Option<String>,
+    // 可以写成{key, value}的列表，也可以写成KEY: value的映射
+    #[serde(default, deserialize_with = "deserialize_define_items")]
+    #[schemars(with = "DefineItemsSchema")]
     pub define_items: Vec<DefineItem>,
-    pub command: Vec<Command>,
+    #[serde(default)]
+    pub command: Vec<CommandItem>,
+    // ${env:NAME} 引用的环境变量不存在时是否报错，为false时原样保留
+    #[serde(default)]
+    pub strict_env: bool,
+    // 对所有copy命令生效的排除规则（glob），与gitignore规则同时生效
+    // 不含路径分隔符的模式匹配任意一级的文件名或目录名，例如 .DS_Store、*~
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global_exclude: Option<Vec<String>>,
+    // 对每一组变量分别执行一遍全部命令，组中的变量优先于其他定义，只在主配置文件中生效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<Vec<BTreeMap<String, String>>>,
+    // 变量替换之后求值的条件，不成立时不执行任何命令，语法见evaluate_condition
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_if: Option<String>,
+    // 对所有run命令（包括命令组中的）生效的默认设置，命令中设置的值优先
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_defaults: Option<RunDefaults>,
+    // 按环境名称分组的命令字段修改，由--env选择其中一组，只在主配置文件中生效
+    // 键为"<命令名称或序号>.<字段>"，字段可以是用.分隔的路径，值按yaml解析
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overrides: Option<BTreeMap<String, BTreeMap<String, String>>>,
+    // 按环境名称定义的配置，由--env选择其中一个，只在主配置文件中生效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profiles: Option<BTreeMap<String, Profile>>,
+}
+
+// 一个环境的配置
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
+pub struct Profile {
+    // 追加的定义项，优先于配置文件中的所有定义，与Config中的define_items一样可以写成映射
+    #[serde(default, deserialize_with = "deserialize_define_items")]
+    #[schemars(with = "DefineItemsSchema")]
+    pub define_items: Vec<DefineItem>,
+    // 只执行name在列表中的命令，为空表示执行全部命令
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commands: Option<Vec<String>>,
+}
+
+// run命令的默认设置，含义与Run中的同名字段相同
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
+pub struct RunDefaults {
+    // 与命令中的env合并，同名的变量以命令中的为准
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_shell_fallback: Option<bool>,
 }
 
 // 定义一个结构体，表示定义项
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug)]
 pub struct DefineItem {
     pub key: String,
+    // 设置了from_command时可以省略
+    #[serde(default)]
     pub value: String,
+    // 解析配置时在配置文件所在目录执行该命令，以去掉首尾空白的stdout作为变量的值
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_command: Option<String>,
+    // 仅在指定的目标系统上生效（windows、linux、macos），为空表示所有系统通用
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    // 值为敏感信息，导出配置时替换为***
+    #[serde(default)]
+    pub secret: bool,
+    // 值本身是通配符模式，引用在通配符字段中时不转义其中的[、*等字符
+    #[serde(default)]
+    pub pattern: bool,
+}
+
+// define_items的两种写法，只用于生成JSON Schema
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[schemars(rename = "DefineItems")]
+#[allow(dead_code)]
+enum DefineItemsSchema {
+    List(Vec<DefineItem>),
+    Map(BTreeMap<String, String>),
+}
+
+// 反序列化define_items，列表中的每一项是一个DefineItem，映射中的每一对键值是变量名和值
+// 映射的值可以是字符串、数字或布尔值，同一个变量名出现多次时报错
+fn deserialize_define_items<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<DefineItem>, D::Error> {
+    struct DefineItemsVisitor;
+
+    impl<'de> de::Visitor<'de> for DefineItemsVisitor {
+        type Value = Vec<DefineItem>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a list of define items or a map of variable names to values")
+        }
+
+        // 只写了define_items:而没有内容时为空
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(Vec::new())
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(Vec::new())
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+            Vec::deserialize(de::value::SeqAccessDeserializer::new(seq))
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut items: Vec<DefineItem> = Vec::new();
+            while let Some((key, value)) = map.next_entry::<String, serde_yaml::Value>()? {
+                if items.iter().any(|item| item.key == key) {
+                    return Err(de::Error::custom(format!("duplicate define item {}", key)));
+                }
+                let value = match value {
+                    serde_yaml::Value::String(value) => value,
+                    serde_yaml::Value::Number(value) => value.to_string(),
+                    serde_yaml::Value::Bool(value) => value.to_string(),
+                    _ => {
+                        return Err(de::Error::custom(format!(
+                            "define item {} must be a string, number or boolean",
+                            key
+                        )))
+                    }
+                };
+                items.push(DefineItem {
+                    key,
+                    value,
+                    from_command: None,
+                    os: None,
+                    secret: false,
+                    pattern: false,
+                });
+            }
+            Ok(items)
+        }
+    }
+
+    deserializer.deserialize_any(DefineItemsVisitor)
+}
+
+// 筛选出当前系统上生效的定义项
+// 不限系统的定义项排在前面作为默认值，匹配当前系统的定义项排在后面，同名时后者覆盖前者
+fn active_define_items(define_items: &[DefineItem]) -> Vec<&DefineItem> {
+    let generic = define_items.iter().filter(|item| item.os.is_none());
+    let matched = define_items.iter().filter(|item| {
+        item.os
+            .as_deref()
+            .is_some_and(|os| os.eq_ignore_ascii_case(std::env::consts::OS))
+    });
+    generic.chain(matched).collect()
+}
+
+// 定义一个结构体，表示命令列表中的一项，包含命令本身和各命令通用的选项
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug)]
+pub struct CommandItem {
+    #[serde(flatten)]
+    pub command: Command,
+    // 命令所属的阶段，为空表示未分阶段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+    // 命令的执行顺序，任意命令设置了该值时，所有命令按其排序，未设置的视为0，相同时保持原有顺序
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<u32>,
+    // 可选的命令，失败时只输出警告，不计入失败的命令
+    #[serde(default)]
+    pub optional: bool,
+    // 并行组，属于同一组的连续命令同时执行，全部结束后再执行之后的命令
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel_group: Option<String>,
+    // 命令中的相对路径相对于该目录，run命令也在该目录下执行；该目录本身相对于工作路径
+    // 解析配置时直接改写命令中的路径，不切换进程的工作路径
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    // 命令的名称，用于在overrides中引用该命令
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // 执行该命令期间控制台输出的日志级别（trace、debug、info、warn、error、off），覆盖--log-level
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    // 按cwd改写之前的目标路径，供--strict-paths检查配置中写下的路径，不出现在配置文件中
+    #[serde(skip)]
+    pub written_destinations: Option<Vec<String>>,
 }
 
 // 定义一个枚举类来存储命令
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug)]
 #[serde(tag = "type")]
 pub enum Command {
-    Copy(Copy),       // copy命令的变体，关联一个Copy结构体
-    Replace(Replace), // replace命令的变体，关联一个Replace结构体
-    Run(Run),         // run命令的变体，关联一个Run结构体
+    Copy(Copy),                       // copy命令的变体，关联一个Copy结构体
+    Replace(Replace),                 // replace命令的变体，关联一个Replace结构体
+    Run(Run),                         // run命令的变体，关联一个Run结构体
+    Custom(Custom),                   // 自定义命令的变体，关联一个Custom结构体
+    VerifyChecksums(VerifyChecksums), // 校验和检查命令的变体，关联一个VerifyChecksums结构体
+    Group(Group),                     // 命令组的变体，关联一个Group结构体
+    Assert(Assert),                   // 前置条件检查的变体，关联一个Assert结构体
+    SetValue(SetValue),               // 修改结构化文件中的值的变体，关联一个SetValue结构体
+    List(List),                       // 列出文件的变体，关联一个List结构体
+    ManifestVar(ManifestVar),         // 统计目录内容并保存为变量的变体，关联一个ManifestVar结构体
+    GenerateIndex(GenerateIndex),     // 生成目录文件索引的变体，关联一个GenerateIndex结构体
+}
+
+impl Command {
+    // 返回命令的类型名称，用于日志和错误信息
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Command::Copy(_) => "Copy",
+            Command::Replace(_) => "Replace",
+            Command::Run(_) => "Run",
+            Command::Custom(_) => "Custom",
+            Command::VerifyChecksums(_) => "VerifyChecksums",
+            Command::Group(_) => "Group",
+            Command::Assert(_) => "Assert",
+            Command::SetValue(_) => "SetValue",
+            Command::List(_) => "List",
+            Command::ManifestVar(_) => "ManifestVar",
+            Command::GenerateIndex(_) => "GenerateIndex",
+        }
+    }
+
+    // 用一句话描述命令要做的事情，用于--explain
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        match self {
+            Command::Copy(copy) => {
+                parts.push(format!(
+                    "copy files from {} to {}",
+                    copy.source, copy.destination
+                ));
+                if copy.use_gitignore {
+                    parts.push(format!("honoring {}", copy.gitignore_path));
+                }
+                if let Some(template) = &copy.destination_template {
+                    parts.push(format!("placing each file at {}", template));
+                }
+                if let Some(prefix) = &copy.add_prefix {
+                    parts.push(format!("prefixing file names with {}", prefix));
+                }
+                if let Some(suffix) = &copy.add_suffix {
+                    parts.push(format!("suffixing file names with {}", suffix));
+                }
+                if copy.copy_empty_dirs {
+                    parts.push("including empty directories".to_string());
+                }
+                if copy.incremental {
+                    parts.push("skipping unchanged files".to_string());
+                }
+                if copy.substitute {
+                    parts.push("expanding variables in text files".to_string());
+                }
+                match copy.link {
+                    Some(LinkMode::Hard) => parts.push("as hard links".to_string()),
+                    Some(LinkMode::Symbolic) => parts.push("as symbolic links".to_string()),
+                    None => {}
+                }
+                if copy.text_only {
+                    parts.push("text files only".to_string());
+                }
+                if copy.binary_only {
+                    parts.push("binary files only".to_string());
+                }
+                if copy.allow_missing_source {
+                    parts.push("skipped if the source is missing".to_string());
+                }
+                if let Some(modes) = &copy.modes {
+                    let modes = modes
+                        .iter()
+                        .map(|rule| format!("{} for {}", rule.mode, rule.pattern))
+                        .join(", ");
+                    parts.push(format!("setting mode {}", modes));
+                }
+            }
+            Command::Replace(replace) if replace.rename_paths => {
+                parts.push(format!(
+                    "rename files and directories under {} matching \"{}\" to \"{}\"",
+                    replace.source, replace.regex, replace.replacement
+                ));
+            }
+            Command::Replace(replace) => {
+                match &replace.replacements {
+                    Some(rules) => parts.push(format!(
+                        "apply {} replacement(s) in {}: {}",
+                        rules.len(),
+                        replace.source,
+                        rules
+                            .iter()
+                            .map(|rule| format!("\"{}\" with \"{}\"", rule.regex, rule.replacement))
+                            .join(", ")
+                    )),
+                    None => parts.push(format!(
+                        "replace \"{}\" with \"{}\" in {}",
+                        replace.regex, replace.replacement, replace.source
+                    )),
+                }
+                if replace.binary {
+                    parts.push("byte-for-byte in binary mode".to_string());
+                }
+                if let Some(transform) = replace.transform {
+                    parts.push(format!("applying {:?} to each replacement", transform));
+                }
+            }
+            Command::Run(run) => {
+                parts.push(format!("run `{}`", run.command));
+                if let Some(stdin_file) = &run.stdin_file {
+                    parts.push(format!("feeding {} to stdin", stdin_file));
+                } else if run.stdin.is_some() {
+                    parts.push("with input on stdin".to_string());
+                }
+                if let Some(stdout_file) = &run.stdout_file {
+                    parts.push(format!("saving stdout to {}", stdout_file));
+                }
+                if let Some(files) = &run.cache_key_files {
+                    parts.push(format!("skipped when {} are unchanged", files.join(", ")));
+                }
+                if run.clear_env {
+                    parts.push(format!(
+                        "in a clean environment keeping [{}]",
+                        run.keep_env.join(", ")
+                    ));
+                }
+                if !run.env.is_empty() {
+                    parts.push(format!("with {}", run.env.keys().join(", ")));
+                }
+                if run.retries > 0 {
+                    match &run.retry_on {
+                        Some(retry_on) => parts.push(format!(
+                            "retrying up to {} time(s) when stderr matches \"{}\"",
+                            run.retries, retry_on
+                        )),
+                        None => parts.push(format!("retrying up to {} time(s)", run.retries)),
+                    }
+                }
+                if let Some(expect_stdout) = &run.expect_stdout {
+                    parts.push(format!("expecting stdout to match \"{}\"", expect_stdout));
+                }
+                if let Some(expect_stderr) = &run.expect_stderr {
+                    parts.push(format!("expecting stderr to match \"{}\"", expect_stderr));
+                }
+                if run.fail_on_stderr {
+                    parts.push("failing if anything is written to stderr".to_string());
+                }
+                if let Some(name) = &run.capture_exit_into {
+                    parts.push(format!(
+                        "storing the exit code in ${{{}}} instead of failing",
+                        name
+                    ));
+                }
+            }
+            Command::Custom(custom) => {
+                parts.push(format!("run custom handler {}", custom.handler));
+                if !custom.args.is_empty() {
+                    let args = custom
+                        .args
+                        .iter()
+                        .sorted()
+                        .map(|(key, value)| format!("{}={}", key, value))
+                        .join(" ");
+                    parts.push(format!("with {}", args));
+                }
+            }
+            Command::VerifyChecksums(verify) => {
+                parts.push(format!(
+                    "verify files in {} against {}",
+                    verify.base_dir, verify.checksums_file
+                ));
+            }
+            Command::Group(group) => {
+                parts.push(format!(
+                    "run {} command(s) as a group ({})",
+                    group.commands.len(),
+                    group.commands.iter().map(Command::describe).join("; ")
+                ));
+                if group.retries > 0 {
+                    parts.push(format!(
+                        "retrying the whole group up to {} time(s) {} second(s) apart",
+                        group.retries, group.delay_secs
+                    ));
+                }
+            }
+            Command::Assert(assert) => {
+                let expectation = if assert.exists {
+                    "exists"
+                } else {
+                    "does not exist"
+                };
+                parts.push(format!(
+                    "check that {} {} {}",
+                    assert.kind_name(),
+                    assert.path,
+                    expectation
+                ));
+            }
+            Command::SetValue(set_value) => {
+                parts.push(format!(
+                    "set {} to \"{}\" in {}",
+                    set_value.key_path, set_value.value, set_value.path
+                ));
+            }
+            Command::List(list) => {
+                parts.push(format!(
+                    "list the files a copy from {} would select",
+                    list.source
+                ));
+                if list.use_gitignore {
+                    parts.push(format!("honoring {}", list.gitignore_path));
+                }
+            }
+            Command::ManifestVar(manifest) => {
+                parts.push(format!(
+                    "summarize the files in {} into ${{{}}}",
+                    manifest.directory, manifest.into
+                ));
+            }
+            Command::GenerateIndex(index) => {
+                parts.push(format!(
+                    "write a {} index of the files in {} to {}",
+                    index.format.name(),
+                    index.directory,
+                    index.output
+                ));
+            }
+        }
+        parts.join(", ")
+    }
+
+    // 返回命令读取的源路径，包含通配符时返回通配符之前的目录
+    pub fn sources(&self) -> Vec<PathBuf> {
+        let source_root = |source: &str| {
+            let root = if is_glob_pattern(source) {
+                glob_base(source)
+            } else {
+                PathBuf::from(source)
+            };
+            if root.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                root
+            }
+        };
+        match self {
+            Command::Copy(copy) => vec![source_root(&copy.source)],
+            Command::Replace(replace) if replace.source == STDIO_SOURCE => Vec::new(),
+            Command::Replace(replace) => vec![source_root(&replace.source)],
+            Command::GenerateIndex(index) => vec![source_root(&index.directory)],
+            Command::Group(group) => group.commands.iter().flat_map(Command::sources).collect(),
+            Command::Run(_)
+            | Command::Custom(_)
+            | Command::VerifyChecksums(_)
+            | Command::Assert(_)
+            | Command::SetValue(_)
+            | Command::List(_)
+            | Command::ManifestVar(_) => Vec::new(),
+        }
+    }
+
+    // 返回命令中所有表示输出目标路径的字段
+    pub fn destinations(&self) -> Vec<&str> {
+        match self {
+            Command::Copy(copy) => vec![&copy.destination],
+            Command::GenerateIndex(index) => vec![&index.output],
+            Command::Group(group) => group
+                .commands
+                .iter()
+                .flat_map(Command::destinations)
+                .collect(),
+            Command::Replace(_)
+            | Command::Run(_)
+            | Command::Custom(_)
+            | Command::VerifyChecksums(_)
+            | Command::Assert(_)
+            | Command::SetValue(_)
+            | Command::List(_)
+            | Command::ManifestVar(_) => Vec::new(),
+        }
+    }
+
+    // 返回命令中所有表示输出目标路径的字段，用于修改
+    pub fn destinations_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Command::Copy(copy) => vec![&mut copy.destination],
+            Command::GenerateIndex(index) => vec![&mut index.output],
+            Command::Group(group) => group
+                .commands
+                .iter_mut()
+                .flat_map(Command::destinations_mut)
+                .collect(),
+            Command::Replace(_)
+            | Command::Run(_)
+            | Command::Custom(_)
+            | Command::VerifyChecksums(_)
+            | Command::Assert(_)
+            | Command::SetValue(_)
+            | Command::List(_)
+            | Command::ManifestVar(_) => Vec::new(),
+        }
+    }
+
+    // 返回命令中所有表示文件路径的字段，包括源路径和目标路径，用于修改
+    pub fn paths_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Command::Copy(copy) => vec![&mut copy.source, &mut copy.destination],
+            Command::Replace(replace) if replace.source == STDIO_SOURCE => Vec::new(),
+            Command::Replace(replace) => vec![&mut replace.source],
+            Command::Run(run) => run
+                .stdin_file
+                .iter_mut()
+                .chain(run.stdout_file.iter_mut())
+                .chain(run.cwd.iter_mut())
+                .collect(),
+            Command::VerifyChecksums(verify) => {
+                vec![&mut verify.checksums_file, &mut verify.base_dir]
+            }
+            Command::Assert(assert) => vec![&mut assert.path],
+            Command::SetValue(set_value) => vec![&mut set_value.path],
+            Command::List(list) => vec![&mut list.source],
+            Command::ManifestVar(manifest) => vec![&mut manifest.directory],
+            Command::GenerateIndex(index) => vec![&mut index.directory, &mut index.output],
+            Command::Group(group) => group
+                .commands
+                .iter_mut()
+                .flat_map(Command::paths_mut)
+                .collect(),
+            Command::Custom(_) => Vec::new(),
+        }
+    }
 }
 
 // 定义一个结构体来存储copy命令的参数
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
 pub struct Copy {
     pub source: String,
     pub destination: String,
     pub gitignore_path: String,
     pub use_gitignore: bool,
+    // 是否在目标路径中创建源路径下的空目录
+    #[serde(default)]
+    pub copy_empty_dirs: bool,
+    // 增量模式，跳过大小相同且目标文件不比源文件旧的文件
+    #[serde(default)]
+    pub incremental: bool,
+    // 目标路径模板，设置后每个文件复制到 destination/展开后的模板 处
+    // 支持的占位符：{relpath} 相对路径、{name} 文件名、{stem} 不含扩展名的文件名、{ext} 扩展名
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination_template: Option<String>,
+    // 复制时展开文本文件中的${NAME}变量，二进制文件（含有\0字节）原样复制
+    #[serde(default)]
+    pub substitute: bool,
+    // 以链接代替复制，为空表示复制文件
+    // 硬链接与源文件共享内容，之后对目标文件的修改（例如replace）也会修改源文件
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<LinkMode>,
+    // 只复制文本文件，根据文件开头是否含有\0字节判断，不依赖扩展名
+    #[serde(default)]
+    pub text_only: bool,
+    // 只复制二进制文件，与text_only相反，两者不能同时设置
+    #[serde(default)]
+    pub binary_only: bool,
+    // 按文件匹配的规则设置目标文件的权限，使用第一条匹配的规则，没有匹配的文件保留复制得到的权限
+    // 只在Unix上生效，不能与link同时使用
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modes: Option<Vec<ModeRule>>,
+    // 添加到每个目标文件名开头的前缀
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub add_prefix: Option<String>,
+    // 添加到每个目标文件名扩展名之前的后缀，例如 app.exe 加上 -1.0 后为 app-1.0.exe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub add_suffix: Option<String>,
+    // 源路径不存在（或glob没有匹配到任何文件）时只输出警告，不视为失败
+    #[serde(default)]
+    pub allow_missing_source: bool,
+    // 设置后使用该大小的缓冲区逐块读写来复制文件，代替fs::copy，用于默认复制方式较慢的网络文件系统
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copy_buffer_bytes: Option<usize>,
+    // 复制完成后比较每个目标文件与源文件的大小，发现磁盘已满等原因造成的截断
+    #[serde(default)]
+    pub verify: bool,
+    // 校验时同时比较sha256，隐含verify
+    #[serde(default)]
+    pub verify_hash: bool,
+    // 复制完成后在目标路径下所有空目录中创建.gitkeep文件，使空目录可以提交到git
+    #[serde(default)]
+    pub gitkeep_empty_dirs: bool,
+    // 复制之前检查目标路径所在文件系统的可用空间，少于该值或少于要复制的文件总大小时报错
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_free_bytes: Option<u64>,
+}
+
+// 文件权限规则
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug)]
+pub struct ModeRule {
+    // glob模式，匹配目标文件相对于destination的路径或文件名，例如 *.sh
+    pub pattern: String,
+    // 八进制的权限，例如 755
+    pub mode: String,
+}
+
+// 链接的方式
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Clone, Copy)]
+pub enum LinkMode {
+    Hard,     // 硬链接，跨文件系统时退回到复制
+    Symbolic, // 指向源文件绝对路径的符号链接
 }
 
 // 定义一个结构体来存储replace命令的参数
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
 pub struct Replace {
     pub source: String,
+    // 设置了replacements时省略
+    #[serde(default)]
     pub regex: String,
+    #[serde(default)]
     pub replacement: String,
+    // 正则表达式编译后的大小上限（字节），用于处理超大的正则
+    // 调大该值会占用更多内存，以换取处理复杂模式的能力
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex_size_limit: Option<usize>,
+    // 正则匹配时DFA缓存的大小上限（字节），调大同样以内存换取对复杂模式和大文件的处理能力
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex_dfa_size_limit: Option<usize>,
+    // 二进制模式，按字节读取文件并替换，可用于修改可执行文件中写死的路径等
+    // 为了不破坏文件中的偏移量，替换后的内容必须与匹配到的内容等长，较短时在末尾补\0，较长时报错
+    #[serde(default)]
+    pub binary: bool,
+    // 重命名模式，不修改文件内容，而是对source目录下名称匹配正则的文件和目录进行重命名
+    #[serde(default)]
+    pub rename_paths: bool,
+    // 对每处匹配按replacement展开后再进行的转换，不能用于二进制模式和重命名模式
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<Transform>,
+    // 文件的编码，例如 UTF-16LE、windows-1252、GBK，按该编码解码后替换，再按原编码写回，为空表示UTF-8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    // 多条替换规则，每个文件只读写一次，按顺序应用所有规则，不能与regex同时使用
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replacements: Option<Vec<ReplaceRule>>,
+}
+
+// replace命令中的一条替换规则
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug)]
+pub struct ReplaceRule {
+    pub regex: String,
+    pub replacement: String,
+}
+
+// replace命令支持的内置转换
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Transform {
+    Upper,     // 转换为大写
+    Lower,     // 转换为小写
+    Increment, // 将最后一个整数加1，保留前导0的宽度，例如 build-009 变为 build-010
+    Base64,    // 进行base64编码
+    Trim,      // 去掉首尾空白
+}
+
+impl Transform {
+    // 对一处匹配展开后的文本进行转换
+    fn apply(self, text: &str) -> Result<String> {
+        match self {
+            Transform::Upper => Ok(text.to_uppercase()),
+            Transform::Lower => Ok(text.to_lowercase()),
+            Transform::Increment => {
+                let re = Regex::new(r"\d+").unwrap();
+                let number = re
+                    .find_iter(text)
+                    .last()
+                    .ok_or_else(|| anyhow!("No number to increment in \"{}\"", text))?;
+                let value = number
+                    .as_str()
+                    .parse::<u64>()?
+                    .checked_add(1)
+                    .ok_or_else(|| anyhow!("Number overflow in \"{}\"", text))?;
+                Ok(format!(
+                    "{}{:0width$}{}",
+                    &text[..number.start()],
+                    value,
+                    &text[number.end()..],
+                    width = number.len()
+                ))
+            }
+            Transform::Base64 => Ok(base64::engine::general_purpose::STANDARD.encode(text)),
+            Transform::Trim => Ok(text.trim().to_string()),
+        }
+    }
 }
 
 // 定义一个结构体来存储run命令的参数
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
 pub struct Run {
     pub command: String,
+    // 写入到命令标准输入的字符串
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<String>,
+    // 将该文件的内容写入到命令的标准输入，不能与stdin同时设置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin_file: Option<String>,
+    // 命令失败后的最大重试次数
+    #[serde(default)]
+    pub retries: u32,
+    // 只有命令失败时的stderr匹配该正则才会重试，为空表示总是重试
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_on: Option<String>,
+    // 命令成功后，stdout必须匹配该正则，否则视为失败
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_stdout: Option<String>,
+    // 命令成功后，stderr必须匹配该正则，否则视为失败
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_stderr: Option<String>,
+    // 命令成功但向stderr输出了内容时视为失败
+    #[serde(default)]
+    pub fail_on_stderr: bool,
+    // fail_on_stderr为true时，匹配该正则的stderr行被忽略，例如进度信息
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignore_stderr_pattern: Option<String>,
+    // 将命令的退出码保存到该变量中，之后的命令可以通过${NAME}引用
+    // 设置后退出码非0不再视为失败，也不会重试
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_exit_into: Option<String>,
+    // 不继承当前进程的环境变量，只保留keep_env中列出的变量
+    #[serde(default)]
+    pub clear_env: bool,
+    // clear_env为true时，仍然从当前进程继承的环境变量，例如PATH
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keep_env: Vec<String>,
+    // 为命令设置的环境变量，在clear_env之后设置，因此总是生效
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    // 直接启动命令失败时，是否再通过cmd /C或sh -c执行，为空表示是
+    // 设置为false时直接返回启动失败的原因，例如找不到可执行文件
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_shell_fallback: Option<bool>,
+    // 日志中最多输出的stdout和stderr字节数，超出的部分被截断，为空时使用--max-output-bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_bytes: Option<usize>,
+    // 命令输出的编码，例如 GBK，按该编码解码stdout和stderr后再输出和匹配，为空表示UTF-8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_encoding: Option<String>,
+    // 将完整的stdout写入该文件，不受截断的影响
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout_file: Option<String>,
+    // 命令的输入文件，可以包含通配符；设置后按命令和这些文件的内容计算缓存键，
+    // 与之前成功执行时的缓存键相同时跳过命令，用于确定性的耗时步骤，例如代码生成
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_key_files: Option<Vec<String>>,
+    // 执行命令时的工作路径，为空表示当前工作路径
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+}
+
+// 定义一个结构体来存储自定义命令的参数，由外部程序完成具体的打包步骤
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
+pub struct Custom {
+    // 处理该命令的可执行文件路径
+    pub handler: String,
+    // 传给处理程序的参数，以json写入标准输入，同时设置为AUTOPACKAGER_ARG_<KEY>环境变量
+    #[serde(default)]
+    pub args: HashMap<String, String>,
+}
+
+// 定义一个结构体来存储校验和检查命令的参数
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
+pub struct VerifyChecksums {
+    // sha256sum格式的校验和文件，每行为"<sha256>  <路径>"
+    pub checksums_file: String,
+    // 校验和文件中的相对路径所相对的目录
+    pub base_dir: String,
+}
+
+// 定义一个结构体来存储命令组的参数，组内任意一条命令失败时从头重试整个组
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
+pub struct Group {
+    // 按顺序执行的命令
+    pub commands: Vec<Command>,
+    // 整个组失败后的最大重试次数
+    #[serde(default)]
+    pub retries: u32,
+    // 每次重试之前等待的秒数
+    #[serde(default)]
+    pub delay_secs: u64,
+}
+
+// 定义一个结构体来存储前置条件检查命令的参数，用于在执行耗时的步骤之前确认所需的文件存在
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
+pub struct Assert {
+    // 要检查的路径，可以包含通配符，此时至少匹配到一个路径即视为存在
+    pub path: String,
+    // 期望路径存在还是不存在
+    pub exists: bool,
+    // 只把文件视为存在
+    #[serde(default)]
+    pub is_file: bool,
+    // 只把目录视为存在，与is_file不能同时设置
+    #[serde(default)]
+    pub is_dir: bool,
+}
+
+impl Assert {
+    // 路径是否满足is_file和is_dir的限定
+    fn matches(&self, path: &Path) -> bool {
+        if self.is_file {
+            path.is_file()
+        } else if self.is_dir {
+            path.is_dir()
+        } else {
+            path.exists()
+        }
+    }
+
+    // 检查的路径类型，用于描述和错误信息
+    fn kind_name(&self) -> &'static str {
+        if self.is_file {
+            "file"
+        } else if self.is_dir {
+            "directory"
+        } else {
+            "path"
+        }
+    }
+}
+
+// 定义一个结构体来存储修改结构化文件中的值的命令的参数
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
+pub struct SetValue {
+    // 要修改的文件，按扩展名判断格式，支持json、yaml和toml
+    pub path: String,
+    // 用.分隔的键路径，数组元素用下标表示，例如 package.version、targets.0.name
+    pub key_path: String,
+    // 设置的值，总是作为字符串写入
+    pub value: String,
+}
+
+// 定义一个结构体来存储list命令的参数，与copy的遍历相关的字段含义相同
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
+pub struct List {
+    pub source: String,
+    #[serde(default)]
+    pub gitignore_path: String,
+    #[serde(default)]
+    pub use_gitignore: bool,
+}
+
+// 定义一个结构体来存储manifestvar命令的参数
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
+pub struct ManifestVar {
+    // 要统计的目录，包括其下所有子目录中的文件
+    pub directory: String,
+    // 保存统计结果的变量名，之后的命令可以通过${NAME}引用
+    pub into: String,
+}
+
+// 定义一个结构体来存储generateindex命令的参数
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default)]
+pub struct GenerateIndex {
+    // 要列出的目录，包括其下所有子目录中的文件
+    pub directory: String,
+    // 写入索引的文件，位于directory中时不列出它自己
+    pub output: String,
+    pub format: IndexFormat,
+}
+
+// 文件索引的格式
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexFormat {
+    #[default]
+    Html, // 带有指向各文件的相对链接的html页面
+    Json, // {"files": [{"path", "size"}]}
+    Text, // 每行一个文件，路径和大小以tab分隔
+}
+
+impl IndexFormat {
+    fn name(self) -> &'static str {
+        match self {
+            IndexFormat::Html => "html",
+            IndexFormat::Json => "json",
+            IndexFormat::Text => "text",
+        }
+    }
 }
 
 // 定义一个函数来执行copy命令
-pub fn execute_copy(copy: &Copy) -> Result<()> {
+pub fn execute_copy(copy: &Copy, options: &ExecuteOptions) -> Result<()> {
     // 输出提示
     info!(
         "*** Copying files from {} to {}",
@@ -66,290 +932,6956 @@ pub fn execute_copy(copy: &Copy) -> Result<()> {
     );
     trace!("- Using gitignore file at {}", copy.gitignore_path);
     trace!("- Using gitignore rules? {}", copy.use_gitignore);
+    if copy.substitute && copy.link.is_some() {
+        return Err(anyhow!("substitute and link cannot be used together"));
+    }
+    if copy.text_only && copy.binary_only {
+        return Err(anyhow!("text_only and binary_only cannot be used together"));
+    }
+    // 链接与源文件共享权限，修改链接的权限会同时修改源文件
+    if copy.modes.is_some() && copy.link.is_some() {
+        return Err(anyhow!("modes and link cannot be used together"));
+    }
+    if copy.copy_buffer_bytes == Some(0) {
+        return Err(anyhow!("copy_buffer_bytes must be greater than 0"));
+    }
+    // 展开变量后的内容与源文件不同，无法比较
+    if (copy.verify || copy.verify_hash) && copy.substitute {
+        return Err(anyhow!("verify and substitute cannot be used together"));
+    }
 
-    if !Path::new(&copy.source).exists() {
-        return Err(anyhow!("No such source directory"));
+    // 展开文件内容时，捕获的变量与解析配置时的变量一起使用
+    let mut variables = options.variables.clone();
+    variables.extend(options.captured_variables());
+    let mut context = CopyContext::new(copy, options, &variables)?;
+    let start = options.profile.then(Instant::now);
+
+    // 允许源路径不存在时，直接跳过该命令
+    if (copy.allow_missing_source || options.allow_missing_source) && !source_exists(&copy.source)?
+    {
+        warn!("- Source {} does not exist, skipping", copy.source);
+        return Ok(());
     }
 
-    // 创建一个WalkBuilder迭代器，遍历源路径下的所有文件和目录
-    let walker = if copy.use_gitignore {
-        // 如果copy.use_gitignore为true，则添加ignore文件
-        WalkBuilder::new(&copy.source)
-            .add_custom_ignore_filename(&copy.gitignore_path)
-            .clone()
+    if let Some(min_free_bytes) = copy.min_free_bytes.or(options.min_free_bytes) {
+        check_free_space(copy, options, &variables, min_free_bytes)?;
+    }
+
+    walk_source(&mut context)?;
+
+    if context.incremental {
+        info!(
+            "- {} file(s) copied, {} unchanged file(s) skipped",
+            context.copied, context.skipped
+        );
+    }
+
+    // 总耗时减去复制文件的耗时，即为遍历和匹配文件的耗时
+    if let Some(start) = start {
+        let total = start.elapsed();
+        options.record_profile(format!(
+            "Copy {} -> {}: total {:.3?}, walk {:.3?}, copy {:.3?}, {} file(s) copied, {} skipped, {} byte(s) moved",
+            copy.source,
+            copy.destination,
+            total,
+            total.saturating_sub(context.copy_time),
+            context.copy_time,
+            context.copied,
+            context.skipped,
+            context.bytes
+        ));
+    }
+
+    // 汇总复制失败的文件
+    if !context.failed.is_empty() {
+        return Err(anyhow!(
+            "{} file(s) failed to copy:\n{}",
+            context.failed.len(),
+            context.failed.join("\n")
+        ));
+    }
+
+    if copy.verify || copy.verify_hash {
+        verify_copied(&context.transferred, copy.verify_hash)?;
+    }
+    if copy.gitkeep_empty_dirs {
+        place_gitkeep(Path::new(&copy.destination))?;
+    }
+    Ok(())
+}
+
+// 在目录下（包括目录本身）所有空目录中创建.gitkeep文件，目标路径不是目录时不做任何事
+fn place_gitkeep(root: &Path) -> Result<()> {
+    if !root.is_dir() {
+        return Ok(());
+    }
+    let mut placed = 0;
+    for entry in WalkBuilder::new(root).standard_filters(false).build() {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type().is_some_and(|ft| ft.is_dir())
+            && fs::read_dir(path)
+                .with_context(|| format!("Failed to read directory {}", path.display()))?
+                .next()
+                .is_none()
+        {
+            let gitkeep = path.join(".gitkeep");
+            fs::write(&gitkeep, "")
+                .with_context(|| format!("Failed to create {}", gitkeep.display()))?;
+            trace!("- Created {}", gitkeep.display());
+            placed += 1;
+        }
+    }
+    if placed > 0 {
+        info!("- Created .gitkeep in {} empty directory(s)", placed);
+    }
+    Ok(())
+}
+
+// 比较复制得到的每个目标文件与源文件的大小（以及sha256），汇总所有不一致的文件
+fn verify_copied(transferred: &[(PathBuf, PathBuf)], compare_hash: bool) -> Result<()> {
+    info!("- Verifying {} copied file(s)", transferred.len());
+    let mut mismatches = Vec::new();
+    for (source_path, target_path) in transferred {
+        let mismatch = copy_mismatch(source_path, target_path, compare_hash)
+            .unwrap_or_else(|e| Some(format!("{:#}", e)));
+        if let Some(reason) = mismatch {
+            mismatches.push(format!("{}: {}", target_path.display(), reason));
+        }
+    }
+    if !mismatches.is_empty() {
+        return Err(anyhow!(
+            "{} file(s) failed verification:\n{}",
+            mismatches.len(),
+            mismatches.join("\n")
+        ));
+    }
+    Ok(())
+}
+
+// 返回目标文件与源文件不一致的原因，一致时返回None
+fn copy_mismatch(
+    source_path: &Path,
+    target_path: &Path,
+    compare_hash: bool,
+) -> Result<Option<String>> {
+    let source_len = fs::metadata(source_path)?.len();
+    let target_len = fs::metadata(target_path)?.len();
+    if source_len != target_len {
+        return Ok(Some(format!(
+            "size {} differs from source size {}",
+            target_len, source_len
+        )));
+    }
+    if compare_hash && file_sha256(source_path)? != file_sha256(target_path)? {
+        return Ok(Some("sha256 differs from source".to_string()));
+    }
+    Ok(None)
+}
+
+// 检查目标路径所在文件系统的可用空间，在复制之前发现空间不足，而不是复制到一半时失败
+// 需要的空间按选中的文件的总大小估计，链接不占用空间；目标路径还不存在时检查其最近的已存在的上级目录
+fn check_free_space(
+    copy: &Copy,
+    options: &ExecuteOptions,
+    variables: &HashMap<String, String>,
+    min_free_bytes: u64,
+) -> Result<()> {
+    let required = if copy.link.is_some() {
+        0
     } else {
-        // 否则，不添加ignore文件
-        WalkBuilder::new(&copy.source)
-            .git_ignore(copy.use_gitignore.clone())
-            .ignore(copy.use_gitignore.clone())
-            .git_global(copy.use_gitignore.clone())
-            .clone()
+        let mut context = CopyContext::new(copy, options, variables)?;
+        context.mode = WalkMode::Measure;
+        walk_source(&mut context)?;
+        context.bytes
     };
+    let destination = std::path::absolute(&copy.destination)?;
+    let existing = destination
+        .ancestors()
+        .find(|path| path.exists())
+        .ok_or_else(|| anyhow!("No existing directory for {}", destination.display()))?;
+    let available = fs2::available_space(existing)
+        .with_context(|| format!("Failed to query free space of {}", existing.display()))?;
+    trace!(
+        "- {} byte(s) free at {}, {} byte(s) to copy",
+        available,
+        existing.display(),
+        required
+    );
+    if available < min_free_bytes {
+        return Err(anyhow!(
+            "Only {} byte(s) free at {}, less than min_free_bytes {}",
+            available,
+            existing.display(),
+            min_free_bytes
+        ));
+    }
+    if available < required {
+        return Err(anyhow!(
+            "Only {} byte(s) free at {}, but {} byte(s) need to be copied",
+            available,
+            existing.display(),
+            required
+        ));
+    }
+    Ok(())
+}
 
-    // 创建一个WalkBuilder迭代器，遍历源路径下的所有文件和目录，并添加ignore文件
-    for result in walker.build() {
-        // 处理每个结果，如果是Ok(entry)，则获取entry的路径
-        if let Ok(entry) = result {
-            let entry_path = entry.path();
-
-            // 判断entry是否是文件，如果是，则复制文件到目标路径
-            if entry.file_type().map_or(false, |ft| ft.is_file()) {
-                // 拼接目标路径和entry的相对路径，作为复制的目标路径
-                let relative_path = entry_path.strip_prefix(&copy.source).unwrap();
-                let target_path_str = format!("{}/{}", &copy.destination, relative_path.display());
-                let target_path = Path::new(&target_path_str);
+// 遍历源路径，对选中的每个文件调用copy_file_to
+fn walk_source(context: &mut CopyContext) -> Result<()> {
+    let copy = context.copy;
+    // 源路径中包含通配符时，按照glob模式匹配要复制的文件
+    if is_glob_pattern(&copy.source) {
+        copy_glob(context)?;
+    } else {
+        if !Path::new(&copy.source).exists() {
+            return Err(anyhow!("No such source directory"));
+        }
 
-                // 创建文件的父目录，如果不存在的话
-                if let Some(parent_path) = target_path.parent() {
-                    fs::create_dir_all(parent_path)?;
-                }
-                // 复制文件到目标路径
-                fs::copy(entry_path, &target_path)?;
+        let source = Path::new(&copy.source);
+        if source.is_file() {
+            // 源路径是单个文件
+            if copy.destination_template.is_some() {
+                let file_name = Path::new(source.file_name().unwrap_or_default());
+                copy_file(context, source, file_name)?;
+            } else {
+                let target_path = single_file_target(source, &copy.destination);
+                copy_file_to(context, source, &target_path)?;
             }
         } else {
-            return Err(anyhow!("ERROR: {:?}", result));
+            copy_tree(context, source, source)?;
         }
     }
+    Ok(())
+}
+
+// 定义一个函数来执行list命令，按与copy相同的规则遍历源路径，只输出选中的文件
+pub fn execute_list(list: &List, options: &ExecuteOptions) -> Result<()> {
+    // 输出提示
+    info!("*** Listing files in {}", list.source);
 
+    let copy = Copy {
+        source: list.source.clone(),
+        gitignore_path: list.gitignore_path.clone(),
+        use_gitignore: list.use_gitignore,
+        ..Default::default()
+    };
+    let variables = HashMap::new();
+    let mut context = CopyContext::new(&copy, options, &variables)?;
+    context.incremental = false;
+    context.mode = WalkMode::List;
+    walk_source(&mut context)?;
+    info!("- {} file(s) matched", context.copied);
     Ok(())
 }
 
-// 定义一个函数来执行replace命令
-pub fn execute_replace(replace: &Replace) -> Result<()> {
+// 定义一个函数来执行manifestvar命令，统计目录中的文件数量和总大小，以json保存到变量中
+pub fn execute_manifest_var(manifest: &ManifestVar, options: &ExecuteOptions) -> Result<()> {
     // 输出提示
     info!(
-        "*** Replacing \"{}\" with \"{}\" in {}",
-        replace.regex, replace.replacement, replace.source
+        "*** Summarizing {} into {}",
+        manifest.directory, manifest.into
     );
-    //let newReg=replace.regex.replace("\\", "\\\\");
-    // 创建一个正则表达式对象
-    let regex = Regex::new(&replace.regex)?;
 
-    // 验证源路径glob是否有效
-    let path_list = glob(&replace.source)?;
+    let root = Path::new(&manifest.directory);
+    if !root.is_dir() {
+        return Err(anyhow!("No such directory"));
+    }
+    let (mut files, mut total_bytes) = (0u64, 0u64);
+    for entry in WalkBuilder::new(root).standard_filters(false).build() {
+        let entry = entry?;
+        if entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_file())
+        {
+            files += 1;
+            total_bytes += entry.metadata()?.len();
+        }
+    }
+    let summary = serde_json::json!({ "files": files, "total_bytes": total_bytes }).to_string();
+    info!("- Captured {} into {}", summary, manifest.into);
+    options.capture(&manifest.into, summary);
+    Ok(())
+}
 
-    // 把迭代器转换成Vec
-    let path_vec: Vec<_> = path_list.collect();
-    // 输出长度
-    // println!("路径列表的长度是: {}", path_vec.len());
+// 定义一个函数来执行generateindex命令，列出目录中的文件及其大小，按路径排序后写入索引文件
+pub fn execute_generate_index(index: &GenerateIndex) -> Result<()> {
+    // 输出提示
+    info!(
+        "*** Generating {} index of {} at {}",
+        index.format.name(),
+        index.directory,
+        index.output
+    );
 
-    if path_vec.len() == 0 {
-        return Err(anyhow!("Invalid path. No files found."));
+    let root = Path::new(&index.directory);
+    if !root.is_dir() {
+        return Err(anyhow!("No such directory"));
+    }
+    let output = std::path::absolute(&index.output)?;
+    let mut files = Vec::new();
+    for (relative_path, path) in list_files(root)? {
+        if std::path::absolute(&path)? != output {
+            files.push((relative_path, path.clone(), fs::metadata(&path)?.len()));
+        }
     }
 
-    // 遍历匹配源路径的所有文件
-    for entry in path_vec {
-        match entry {
-            Ok(path) => {
-                if !path.is_file() {
-                    continue;
-                }
-                // 读取文件内容并替换匹配的部分
-                let file_content = fs::read_to_string(&path)?;
-                let replaced_content = regex
-                    .replace_all(&file_content, &replace.replacement)
-                    .to_owned()
-                    .to_string();
-                // 写入新的文件内容
-                fs::write(&path, replaced_content)?;
-            }
-            Err(e) => {
-                return Err(anyhow!("Failed to read glob pattern. {}", e.to_string()));
+    let content = match index.format {
+        IndexFormat::Text => files
+            .iter()
+            .map(|(relative_path, _, size)| format!("{}\t{}\n", relative_path, size))
+            .collect(),
+        IndexFormat::Json => {
+            let files: Vec<_> = files
+                .iter()
+                .map(|(relative_path, _, size)| {
+                    serde_json::json!({ "path": relative_path, "size": size })
+                })
+                .collect();
+            serde_json::to_string_pretty(&serde_json::json!({ "files": files }))?
+        }
+        IndexFormat::Html => {
+            // 链接相对于索引文件所在的目录，因此索引文件不在directory中时也能打开
+            let output_dir = output.parent().unwrap_or(Path::new(""));
+            let title = html_escape(&index.directory);
+            let mut html = format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Index of {0}</title>\n</head>\n<body>\n<h1>Index of {0}</h1>\n<ul>\n",
+                title
+            );
+            for (relative_path, path, size) in &files {
+                let link = relative_link(output_dir, &std::path::absolute(path)?);
+                html.push_str(&format!(
+                    "<li><a href=\"{}\">{}</a> ({} bytes)</li>\n",
+                    encode_link(&link),
+                    html_escape(relative_path),
+                    size
+                ));
             }
+            html.push_str("</ul>\n</body>\n</html>\n");
+            html
         }
-    }
+    };
 
+    if let Some(parent) = Path::new(&index.output).parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    fs::write(&index.output, content)
+        .with_context(|| format!("Failed to write {}", index.output))?;
+    info!("- {} file(s) listed", files.len());
     Ok(())
 }
 
-// 定义一个函数来执行run命令
-pub fn execute_run(run: &Run) -> Result<()> {
-    // 输出提示
-    info!("*** Running command: {}", run.command);
+// 计算从目录到文件的相对路径，以/分隔，用作html中的链接
+fn relative_link(from_dir: &Path, to: &Path) -> String {
+    let from: Vec<_> = from_dir.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    std::iter::repeat_n("..".to_string(), from.len() - common)
+        .chain(
+            to[common..]
+                .iter()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned()),
+        )
+        .join("/")
+}
 
-    let command = &run.command;
-    let words = shell_words::split(command).unwrap(); // 用shell-words库来分割字符串
-    let output = SysCommand::new(&words[0]) // words[0]是命令
-        .args(&words[1..]) // words[1..]是参数组
-        .output() // 执行命令并获取输出
-        .or_else(|_| {
-            // 如果失败了，就用cmd /c来执行
-            if cfg!(target_os = "windows") {
-                SysCommand::new("cmd").arg("/C").args(words).output()
-            } else {
-                SysCommand::new("sh").arg("-c").args(words).output()
+// 对链接中除/和不需要编码的字符之外的字节进行百分号编码
+fn encode_link(link: &str) -> String {
+    link.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
             }
+            _ => format!("%{:02X}", byte),
         })
-        .expect("failed to execute command");
+        .collect()
+}
 
-    // 检查命令是否成功
-    if output.status.success() {
-        // 输出标准输出和标准错误
-        info!("- result: {}", String::from_utf8_lossy(&output.stdout));
-        //println!("stderr: {}", String::from_utf8_lossy(&output.stderr));
-        Ok(())
-    } else {
-        error!("stderr: {}", String::from_utf8_lossy(&output.stderr));
-        // 返回错误值
-        Err(anyhow!("command failed with status: {}", output.status))
-    }
+// 转义html中的特殊字符
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
 }
 
-// 定义一个函数来执行命令列表
-pub fn execute_commands(commands: &[Command]) -> Result<(), Vec<anyhow::Error>> {
-    // 使用partition_map方法来将Result分割成两个集合
-    let (_, errors): (Vec<_>, Vec<_>) = commands
-        .into_iter()
-        .map(|command| match command {
-            Command::Copy(copy) => execute_copy(copy),
-            Command::Replace(replace) => execute_replace(replace),
-            Command::Run(run) => execute_run(run),
+// 一次copy命令执行过程中的状态
+struct CopyContext<'a> {
+    copy: &'a Copy,
+    // 全局排除规则，匹配到的文件不复制
+    exclude: GlobSet,
+    // 编译后的权限规则
+    modes: Vec<(GlobMatcher, u32)>,
+    // 取消标志，被设置后中止复制
+    cancelled: &'a AtomicBool,
+    // 是否跳过未改变的文件
+    incremental: bool,
+    // 实际复制的文件数量
+    copied: usize,
+    // 增量模式下跳过的文件数量
+    skipped: usize,
+    // 是否统计性能数据
+    profile: bool,
+    // 复制的总字节数
+    bytes: u64,
+    // 复制文件本身的总耗时，只在profile为true时统计
+    copy_time: Duration,
+    // 展开文件内容中的变量时使用的变量映射
+    variables: &'a HashMap<String, String>,
+    strict_env: bool,
+    // 为true时遇到第一个复制失败的文件就中止，否则记录下来并继续复制其余的文件
+    fail_fast: bool,
+    // 复制失败的文件及原因
+    failed: Vec<String>,
+    // 对选中的文件要做的事情
+    mode: WalkMode,
+    // 复制（或增量模式下跳过）的源文件和目标文件，只在需要校验时记录
+    transferred: Vec<(PathBuf, PathBuf)>,
+}
+
+// 遍历源路径时对选中的文件要做的事情
+#[derive(Clone, Copy, PartialEq)]
+enum WalkMode {
+    Copy,    // 复制文件
+    List,    // 只输出文件路径，用于list命令
+    Measure, // 只统计文件的总大小，用于检查可用空间
+}
+
+impl<'a> CopyContext<'a> {
+    fn new(
+        copy: &'a Copy,
+        options: &'a ExecuteOptions,
+        variables: &'a HashMap<String, String>,
+    ) -> Result<Self> {
+        Ok(CopyContext {
+            copy,
+            exclude: build_exclude_set(&options.global_exclude)?,
+            modes: build_mode_rules(copy.modes.as_deref().unwrap_or_default())?,
+            cancelled: &options.cancelled,
+            incremental: copy.incremental || options.incremental,
+            profile: options.profile,
+            copied: 0,
+            skipped: 0,
+            bytes: 0,
+            copy_time: Duration::ZERO,
+            variables,
+            strict_env: options.strict_env,
+            fail_fast: options.fail_fast,
+            failed: Vec::new(),
+            mode: WalkMode::Copy,
+            transferred: Vec::new(),
         })
-        // .partition_map(From::from);
-        .partition_map(|r| match r {
-            Ok(v) => itertools::Either::Left(v),
-            Err(v) => {
-                error!("!!! Error occurred: {}", v);
-                itertools::Either::Right(v)
+    }
+}
+
+// 编译全局排除规则
+fn build_exclude_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .with_context(|| format!("Invalid global_exclude pattern {}", pattern))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+// 编译权限规则，权限按八进制解析
+fn build_mode_rules(rules: &[ModeRule]) -> Result<Vec<(GlobMatcher, u32)>> {
+    rules
+        .iter()
+        .map(|rule| {
+            let matcher = Glob::new(&rule.pattern)
+                .with_context(|| format!("Invalid mode pattern {}", rule.pattern))?
+                .compile_matcher();
+            let mode = u32::from_str_radix(rule.mode.trim_start_matches("0o"), 8)
+                .ok()
+                .filter(|mode| *mode <= 0o7777)
+                .ok_or_else(|| anyhow!("Invalid mode {} for {}", rule.mode, rule.pattern))?;
+            Ok((matcher, mode))
+        })
+        .collect()
+}
+
+// 判断相对路径是否被排除：整个相对路径匹配，或者其中任意一级的名称匹配
+// 因此排除一个目录名时，该目录下的所有文件都会被排除
+fn is_excluded(exclude: &GlobSet, relative_path: &Path) -> bool {
+    exclude.is_match(relative_path)
+        || relative_path
+            .components()
+            .any(|component| exclude.is_match(component.as_os_str()))
+}
+
+// 判断源路径是否存在，包含通配符时至少匹配到一个路径即视为存在
+fn source_exists(source: &str) -> Result<bool> {
+    if is_glob_pattern(source) {
+        Ok(glob(source)?.next().is_some())
+    } else {
+        Ok(Path::new(source).exists())
+    }
+}
+
+// 判断字符串中是否包含glob通配符
+fn is_glob_pattern(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+// 计算glob模式中不含通配符的最长前缀，作为保留目录结构的基准路径
+// 例如 build/**/*.wasm 的基准路径是 build，匹配到的 build/a/x.wasm 会被复制到 destination/a/x.wasm
+// 只含转义字符的部分（例如变量值1.0[beta]转义成的1.0[[]beta[]]）按字面的路径计入基准路径
+fn glob_base(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        // glob返回的路径不带开头的 ./，这里也要去掉，否则无法strip_prefix
+        .filter(|component| *component != Component::CurDir)
+        .map_while(|component| unescape_glob(&component.as_os_str().to_string_lossy()))
+        .collect()
+}
+
+// 去掉glob模式中[*]、[[]等形式的转义，得到字面的字符串，含有未转义的通配符时返回None
+fn unescape_glob(pattern: &str) -> Option<String> {
+    let mut literal = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' | '?' => return None,
+            '[' => {
+                let mut escaped = chars.clone();
+                match (escaped.next(), escaped.next()) {
+                    (Some(c @ ('*' | '?' | '[' | ']' | '{' | '}')), Some(']')) => {
+                        literal.push(c);
+                        chars = escaped;
+                    }
+                    _ => return None,
+                }
             }
-        });
-    // 检查错误集合是否为空
-    if errors.is_empty() {
-        // 如果没有错误，就返回Ok(())
-        Ok(())
+            _ => literal.push(c),
+        }
+    }
+    Some(literal)
+}
+
+// 复制glob模式匹配到的所有文件，目录结构相对于模式的基准路径保留
+fn copy_glob(context: &mut CopyContext) -> Result<()> {
+    let base = glob_base(&context.copy.source);
+    trace!("- Using glob base {}", base.display());
+
+    let paths = glob(&context.copy.source)?.collect::<Result<Vec<_>, _>>()?;
+    if paths.is_empty() {
+        return Err(anyhow!("Invalid path. No files found."));
+    }
+
+    // 只匹配到一个文件时，与源路径是单个文件的情况相同
+    if let [path] = paths.as_slice() {
+        if path.is_file() && context.copy.destination_template.is_none() {
+            let target_path = single_file_target(path, &context.copy.destination);
+            return copy_file_to(context, path, &target_path);
+        }
+    }
+
+    for path in paths {
+        if path.is_dir() {
+            // 匹配到目录时，复制整个目录
+            copy_tree(context, &path, &base)?;
+        } else if path.is_file() {
+            let relative_path = path.strip_prefix(&base)?;
+            copy_file(context, &path, relative_path)?;
+        }
+    }
+    Ok(())
+}
+
+// 源路径只对应单个文件时，计算目标文件的路径
+// destination以路径分隔符结尾或者是已存在的目录时，把文件复制到该目录下并保留文件名；
+// 否则destination就是目标文件的路径，相当于复制并重命名，例如 cp foo.txt out/renamed.txt
+fn single_file_target(source_path: &Path, destination: &str) -> PathBuf {
+    let destination_path = Path::new(destination);
+    if destination.ends_with(['/', '\\']) || destination_path.is_dir() {
+        destination_path.join(source_path.file_name().unwrap_or_default())
     } else {
-        // 如果有错误，就返回Err(errors)
-        Err(errors)
+        destination_path.to_path_buf()
     }
 }
 
-// 从yaml文件中反序列化Config
-pub fn parse_commands_from_yaml(file_path: &str, if_use_define: bool) -> Result<Config> {
-    // 从yaml文件中读取内容，并存储为一个字符串
-    let mut yaml_content = fs::read_to_string(file_path)?;
+// 遍历root下的所有文件，按照相对于base的路径复制到目标路径
+fn copy_tree(context: &mut CopyContext, root: &Path, base: &Path) -> Result<()> {
+    let copy = context.copy;
+    // 创建一个WalkBuilder迭代器，遍历源路径下的所有文件和目录
+    let walker = if copy.use_gitignore {
+        // 如果copy.use_gitignore为true，则添加ignore文件
+        WalkBuilder::new(root)
+            .add_custom_ignore_filename(&copy.gitignore_path)
+            .clone()
+    } else {
+        // 否则，不添加ignore文件
+        WalkBuilder::new(root)
+            .git_ignore(copy.use_gitignore)
+            .ignore(copy.use_gitignore)
+            .git_global(copy.use_gitignore)
+            .clone()
+    };
 
-    if if_use_define {
-        // 反序列化为Config结构体
-        let config: Config = deserialize_config(&yaml_content)?;
+    // 创建一个WalkBuilder迭代器，遍历源路径下的所有文件和目录，并添加ignore文件
+    for result in walker.build() {
+        // 处理每个结果，如果是Ok(entry)，则获取entry的路径
+        if let Ok(entry) = result {
+            let entry_path = entry.path();
 
-        // 建立变量名到值的映射关系
-        let mut valuemap = std::collections::HashMap::new();
-        for item in &config.define_items {
-            valuemap.insert(item.key.clone(), item.value.clone());
+            // 判断entry是否是文件，如果是，则复制文件到目标路径
+            if entry.file_type().is_some_and(|ft| ft.is_file()) {
+                let relative_path = entry_path.strip_prefix(base).unwrap();
+                copy_file(context, entry_path, relative_path)?;
+            } else if copy.copy_empty_dirs
+                && context.mode == WalkMode::Copy
+                && entry.file_type().is_some_and(|ft| ft.is_dir())
+                && fs::read_dir(entry_path)
+                    .with_context(|| format!("Failed to read directory {}", entry_path.display()))?
+                    .next()
+                    .is_none()
+            {
+                // 源路径下的空目录，在目标路径中创建对应的目录
+                let relative_path = entry_path.strip_prefix(base).unwrap();
+                if is_excluded(&context.exclude, relative_path) {
+                    continue;
+                }
+                let target_path = Path::new(&copy.destination).join(relative_path);
+                fs::create_dir_all(&target_path).with_context(|| {
+                    format!("Failed to create directory {}", target_path.display())
+                })?;
+            }
+        } else {
+            return Err(anyhow!("ERROR: {:?}", result));
         }
+    }
+
+    Ok(())
+}
+
+// 将单个文件复制到目标路径下的相对路径处
+fn copy_file(context: &mut CopyContext, source_path: &Path, relative_path: &Path) -> Result<()> {
+    if is_excluded(&context.exclude, relative_path) {
+        trace!("- Excluded {}", source_path.display());
+        return Ok(());
+    }
+
+    // 设置了目标路径模板时，按模板计算相对于destination的路径
+    if let Some(template) = &context.copy.destination_template {
+        let target_path = Path::new(&context.copy.destination)
+            .join(expand_destination_template(template, relative_path));
+        return copy_file_to(context, source_path, &target_path);
+    }
+
+    // 拼接目标路径和entry的相对路径，作为复制的目标路径
+    let target_path_str = format!("{}/{}", context.copy.destination, relative_path.display());
+    copy_file_to(context, source_path, Path::new(&target_path_str))
+}
+
+// 展开目标路径模板中的占位符
+// {relpath} 文件相对于源路径的路径，{name} 文件名，{stem} 不含扩展名的文件名，{ext} 不含.的扩展名
+fn expand_destination_template(template: &str, relative_path: &Path) -> String {
+    let part = |value: Option<&std::ffi::OsStr>| {
+        value
+            .map(|value| value.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    template
+        .replace("{relpath}", &relative_path.to_string_lossy())
+        .replace("{name}", &part(relative_path.file_name()))
+        .replace("{stem}", &part(relative_path.file_stem()))
+        .replace("{ext}", &part(relative_path.extension()))
+}
+
+// 将单个文件复制到指定的目标文件路径
+// 复制失败时，除非设置了fail_fast，否则记录失败的文件后继续
+fn copy_file_to(context: &mut CopyContext, source_path: &Path, target_path: &Path) -> Result<()> {
+    if context.cancelled.load(Ordering::SeqCst) {
+        return Err(anyhow!("Cancelled"));
+    }
 
-        // 对valuemap每一项进行遍历，进行变量替换
-        for item in &config.define_items {
-            let subst_value = substitute_variables(&item.value, &valuemap);
-            valuemap.insert(item.key.clone(), subst_value);
+    let target_path = &add_name_affixes(
+        target_path,
+        context.copy.add_prefix.as_deref(),
+        context.copy.add_suffix.as_deref(),
+    );
+    let result = transfer_file(context, source_path, target_path).with_context(|| {
+        format!(
+            "Failed to copy {} to {}",
+            source_path.display(),
+            target_path.display()
+        )
+    });
+    match result {
+        Err(e) if !context.fail_fast => {
+            error!("- {:#}", e);
+            context.failed.push(format!("{:#}", e));
+            Ok(())
         }
+        result => result,
+    }
+}
 
-        // 对指定文本进行变量替换
-        // let mut subst_text = yaml_content.clone();
-        // for item in &config.define_items {
-        //     subst_text = substitute_variables(&subst_text, &valuemap);
-        // }
+// 在文件名开头添加前缀，在扩展名之前添加后缀，不改变所在的目录
+fn add_name_affixes(path: &Path, prefix: Option<&str>, suffix: Option<&str>) -> PathBuf {
+    if prefix.is_none() && suffix.is_none() {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut file_name = format!(
+        "{}{}{}",
+        prefix.unwrap_or_default(),
+        stem,
+        suffix.unwrap_or_default()
+    );
+    if let Some(extension) = path.extension() {
+        file_name.push('.');
+        file_name.push_str(&extension.to_string_lossy());
+    }
+    path.with_file_name(file_name)
+}
 
-        let subst_text = substitute_variables(&yaml_content, &valuemap);
-        println!("{}", subst_text);
-        yaml_content = subst_text;
+// 复制、链接或展开变量后写入单个文件
+fn transfer_file(context: &mut CopyContext, source_path: &Path, target_path: &Path) -> Result<()> {
+    // 按内容类型筛选文件，列出和估计大小时同样只计入会被复制的文件
+    if context.copy.text_only || context.copy.binary_only {
+        let binary = is_binary_file(source_path)?;
+        if binary != context.copy.binary_only {
+            trace!("- Skipping filtered file {}", source_path.display());
+            return Ok(());
+        }
+    }
 
-        // let mut old_config_str = yaml_content.clone();
-        // let mut new_config_str = old_config_str.clone();
+    match context.mode {
+        WalkMode::Copy => {}
+        WalkMode::List => {
+            info!("- {}", source_path.display());
+            context.copied += 1;
+            return Ok(());
+        }
+        WalkMode::Measure => {
+            context.bytes += fs::metadata(source_path)?.len();
+            context.copied += 1;
+            return Ok(());
+        }
+    }
 
-        // 循环替换，直到没有改变为止
-        // loop {
-        //     for item in &config.define_items {
-        //         let key = format!("${{{}}}", item.key);
-        //         let value = &item.value;
-        //         // 使用regex::Regex::new函数创建一个正则表达式对象，用来匹配"{key}"
-        //         let re = Regex::new(&regex::escape(&key)).unwrap();
-        //         new_config_str = re.replace_all(&new_config_str, value).to_string();
-        //     }
+    // 增量模式下，目标文件未改变时跳过
+    // 展开变量的文件内容取决于变量的值，无法通过大小和修改时间判断，总是重新复制
+    if context.incremental && !context.copy.substitute && is_unchanged(source_path, target_path) {
+        trace!("- Skipping unchanged file {}", source_path.display());
+        context.skipped += 1;
+        record_transferred(context, source_path, target_path);
+        return Ok(());
+    }
 
-        //     if !old_config_str.eq(&new_config_str) {
-        //         old_config_str = new_config_str.clone();
-        //     } else {
-        //         break;
-        //     }
-        // }
+    // 创建文件的父目录，如果不存在的话
+    if let Some(parent_path) = target_path.parent() {
+        fs::create_dir_all(parent_path)
+            .with_context(|| format!("Failed to create directory {}", parent_path.display()))?;
+    }
+    // 复制文件到目标路径
+    let start = context.profile.then(Instant::now);
+    context.bytes += if context.copy.substitute {
+        copy_file_substituted(context, source_path, target_path)?
+    } else if let Some(link) = context.copy.link {
+        link_file(link, source_path, target_path)?
+    } else if let Some(buffer_bytes) = context.copy.copy_buffer_bytes {
+        copy_file_buffered(source_path, target_path, buffer_bytes)?
+    } else {
+        fs::copy(source_path, target_path)?
+    };
+    if let Some(start) = start {
+        context.copy_time += start.elapsed();
+    }
+    apply_mode(context, target_path)?;
+    context.copied += 1;
+    record_transferred(context, source_path, target_path);
+    Ok(())
+}
 
-        // for item in &config.define_items {
-        //     let key = format!("${{{}}}", item.key);
-        //     let value = &item.value;
-        //     // 使用regex::Regex::new函数创建一个正则表达式对象，用来匹配"{key}"
-        //     let re = Regex::new(&regex::escape(&key)).unwrap();
-        //     new_config_str = re.replace_all(&new_config_str, value).to_string();
-        // }
+// 需要校验时记录复制的文件
+fn record_transferred(context: &mut CopyContext, source_path: &Path, target_path: &Path) {
+    if context.copy.verify || context.copy.verify_hash {
+        context
+            .transferred
+            .push((source_path.to_path_buf(), target_path.to_path_buf()));
+    }
+}
 
-        // let re = regex::Regex::new(r"$\{(\w+)\}").unwrap();
-        // // 如果匹配到常量还存在，说明常量未完全定义
-        // if re.is_match(&new_config_str) {
-        //     return Err(anyhow!("Invalid configuration"));
-        // }
-        // yaml_content = new_config_str;
+// 使用指定大小的缓冲区复制文件，返回复制的字节数
+// 与fs::copy一样覆盖已存在的目标文件，并将源文件的权限复制到目标文件
+fn copy_file_buffered(source_path: &Path, target_path: &Path, buffer_bytes: usize) -> Result<u64> {
+    let source = fs::File::open(source_path)?;
+    let permissions = source.metadata()?.permissions();
+    let mut reader = io::BufReader::with_capacity(buffer_bytes, source);
+    let mut writer = io::BufWriter::with_capacity(buffer_bytes, fs::File::create(target_path)?);
+    let bytes = io::copy(&mut reader, &mut writer)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .set_permissions(permissions)?;
+    Ok(bytes)
+}
+
+// 按第一条匹配的权限规则设置目标文件的权限
+fn apply_mode(context: &CopyContext, target_path: &Path) -> Result<()> {
+    let file_name = Path::new(target_path.file_name().unwrap_or_default());
+    let relative_path = target_path
+        .strip_prefix(&context.copy.destination)
+        .ok()
+        .filter(|path| !path.as_os_str().is_empty())
+        .unwrap_or(file_name);
+    let rule = context
+        .modes
+        .iter()
+        .find(|(matcher, _)| matcher.is_match(relative_path) || matcher.is_match(file_name));
+    if let Some((_, mode)) = rule {
+        trace!("- Setting mode {:o} on {}", mode, target_path.display());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(target_path, fs::Permissions::from_mode(*mode))
+                .with_context(|| format!("Failed to set mode on {}", target_path.display()))?;
+        }
     }
-    println!("{}", yaml_content);
-    deserialize_config(&yaml_content)
+    Ok(())
 }
 
-// 替换字符串中的变量
-// value 是待替换的字符串。
-// valuemap 是一个 HashMap，用于将变量名映射到变量的值。
-// 使用正则来查找 ${} 形式的变量名，并将变量名替换为变量的值。
-// 在替换变量名时，函数会递归地调用自己来解析变量的值。这是因为变量的值可能包含其他变量名，例如 ${VER_MAJOR}.${VER_MINOR}.${VER_PATCH}.${VER_BUILD}。
-// 在这种情况下，函数会首先替换 ${VER_MAJOR}，然后替换 ${VER_MINOR}，以此类推，直到所有变量都被替换为其对应的值。
-// 请注意，函数会尝试替换所有变量，直到没有新的替换可以进行为止。如果某个变量的值中包含无法解析的变量名，函数将停止替换并返回原始字符串。这种情况可以在循环中检查，如果找不到相应的变量，则可以中断循环。
-// 最后，该函数返回替换后的字符串。
-fn substitute_variables(
-    value: &str,
-    valuemap: &std::collections::HashMap<String, String>,
-) -> String {
-    let re = Regex::new(r"\$\{(\w+)\}").unwrap();
-    let mut result = String::from(value);
-    while let Some(caps) = re.captures(&result) {
-        let var_name = caps.get(1).unwrap().as_str();
-        if let Some(subst_value) = valuemap.get(var_name) {
-            let subst_result = substitute_variables(subst_value, valuemap);
-            let range = caps.get(0).unwrap().range();
-            result.replace_range(range.start..range.end, &subst_result);
-        } else {
-            break;
+// 创建指向源文件的链接，返回复制的字节数，只有硬链接退回到复制时才不为0
+fn link_file(link: LinkMode, source_path: &Path, target_path: &Path) -> Result<u64> {
+    // 与fs::copy一样覆盖已存在的目标文件
+    if fs::symlink_metadata(target_path).is_ok() {
+        fs::remove_file(target_path)
+            .with_context(|| format!("Failed to remove existing {}", target_path.display()))?;
+    }
+    match link {
+        LinkMode::Hard => match fs::hard_link(source_path, target_path) {
+            Ok(()) => Ok(0),
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                trace!(
+                    "- Cannot hard link across filesystems, copying {}",
+                    source_path.display()
+                );
+                Ok(fs::copy(source_path, target_path)?)
+            }
+            Err(e) => Err(e.into()),
+        },
+        LinkMode::Symbolic => {
+            let source_path = std::path::absolute(source_path)?;
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&source_path, target_path)?;
+            #[cfg(windows)]
+            std::os::windows::fs::symlink_file(&source_path, target_path)?;
+            Ok(0)
         }
     }
-    result
 }
 
-// 定义一个函数，用于从yaml字符串反序列化为Config对象
-pub fn deserialize_config(yaml: &str) -> Result<Config> {
-    Ok(serde_yaml::from_str(yaml).unwrap())
+// 复制文件并展开其中的变量，返回写入的字节数
+// 含有\0字节或不是合法UTF-8的文件视为二进制文件，原样复制
+fn copy_file_substituted(
+    context: &CopyContext,
+    source_path: &Path,
+    target_path: &Path,
+) -> Result<u64> {
+    let content = fs::read(source_path)?;
+    let text = match String::from_utf8(content) {
+        Ok(text) if !text.contains('\0') => text,
+        _ => return Ok(fs::copy(source_path, target_path)?),
+    };
+    let text = substitute_variables(&text, context.variables, context.strict_env)
+        .with_context(|| format!("Failed to substitute {}", source_path.display()))?;
+    fs::write(target_path, &text)?;
+    Ok(text.len() as u64)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// 读取文件开头的一部分，含有\0字节时视为二进制文件
+fn is_binary_file(path: &Path) -> io::Result<bool> {
+    let mut buffer = Vec::with_capacity(8192);
+    fs::File::open(path)?.take(8192).read_to_end(&mut buffer)?;
+    Ok(buffer.contains(&0))
+}
 
-    #[test]
-    // 测试yaml文件解析
-    fn parse_correct_commands_test() -> Result<()> {
-        // 从tests/config.yml文件中解析出Config对象
-        let config = parse_commands_from_yaml("tests/ori_data/config.yml", true)?;
-        let expected_config = parse_commands_from_yaml("tests/data/config.yml", false)?;
-        // 使用assert_eq!宏来断言两个Config对象是否相等
-        assert_eq!(config, expected_config);
-        // 如果没有错误，就返回Ok(())
-        Ok(())
+// 判断目标文件相对于源文件是否未改变
+// 两者大小相同，且目标文件的修改时间不早于源文件时，认为源文件在上次复制之后没有改变
+fn is_unchanged(source_path: &Path, target_path: &Path) -> bool {
+    let (Ok(source), Ok(target)) = (fs::metadata(source_path), fs::metadata(target_path)) else {
+        return false;
+    };
+    match (source.modified(), target.modified()) {
+        (Ok(source_modified), Ok(target_modified)) => {
+            source.len() == target.len() && target_modified >= source_modified
+        }
+        _ => false,
     }
+}
 
-    #[test]
-    // 测试命令执行函数
-    fn test_execute_run() {
-        // 创建一个Run结构体实例
-        let run = Run {
-            command: "echo hello".to_string(),
+// 定义一个函数来执行replace命令
+pub fn execute_replace(replace: &Replace, options: &ExecuteOptions) -> Result<()> {
+    // 输出提示
+    match &replace.replacements {
+        Some(rules) => info!(
+            "*** Applying {} replacement(s) in {}",
+            rules.len(),
+            replace.source
+        ),
+        None => info!(
+            "*** Replacing \"{}\" with \"{}\" in {}",
+            replace.regex, replace.replacement, replace.source
+        ),
+    }
+    // 空的正则会匹配每个位置，没有规则时也不应该改写文件，多半是配置写错了键名
+    match &replace.replacements {
+        None if replace.regex.is_empty() => {
+            return Err(anyhow!("regex or replacements is required"));
+        }
+        Some(rules) if rules.is_empty() => {
+            return Err(anyhow!("replacements must not be empty"));
+        }
+        _ => {}
+    }
+    if replace.replacements.is_some() {
+        if !replace.regex.is_empty() {
+            return Err(anyhow!("regex and replacements can not be used together"));
+        }
+        if replace.binary || replace.rename_paths {
+            return Err(anyhow!(
+                "replacements can not be used in binary mode or with rename_paths"
+            ));
+        }
+    }
+    //let newReg=replace.regex.replace("\\", "\\\\");
+    // 创建一个正则表达式对象，如果配置了大小上限则一并设置
+    let regex = if replace.binary {
+        let mut builder = BytesRegexBuilder::new(&replace.regex);
+        if let Some(size_limit) = replace.regex_size_limit {
+            builder.size_limit(size_limit);
+        }
+        if let Some(dfa_size_limit) = replace.regex_dfa_size_limit {
+            builder.dfa_size_limit(dfa_size_limit);
+        }
+        ReplaceRegex::Binary(builder.build()?)
+    } else {
+        let build = |pattern: &str| -> Result<Regex> {
+            let mut builder = RegexBuilder::new(pattern);
+            if let Some(size_limit) = replace.regex_size_limit {
+                builder.size_limit(size_limit);
+            }
+            if let Some(dfa_size_limit) = replace.regex_dfa_size_limit {
+                builder.dfa_size_limit(dfa_size_limit);
+            }
+            Ok(builder.build()?)
+        };
+        let rules = match &replace.replacements {
+            Some(rules) => rules
+                .iter()
+                .map(|rule| Ok((build(&rule.regex)?, rule.replacement.clone())))
+                .collect::<Result<Vec<_>>>()?,
+            None => vec![(build(&replace.regex)?, replace.replacement.clone())],
         };
+        ReplaceRegex::Text(rules)
+    };
 
-        // 调用execute_run函数，并断言它返回Ok(())
-        assert_eq!((), execute_run(&run).unwrap());
+    if replace.transform.is_some() && (replace.binary || replace.rename_paths) {
+        return Err(anyhow!(
+            "transform can not be used in binary mode or with rename_paths"
+        ));
+    }
+    let encoding = replace.encoding.as_deref().map(text_encoding).transpose()?;
+    if encoding.is_some()
+        && (replace.binary || replace.rename_paths || replace.source == STDIO_SOURCE)
+    {
+        return Err(anyhow!(
+            "encoding can not be used in binary mode, with rename_paths or with stdin"
+        ));
+    }
+    if replace.rename_paths && replace.source == STDIO_SOURCE {
+        return Err(anyhow!("rename_paths can not be used with stdin"));
+    }
+
+    // 重命名模式下不修改文件内容，而是替换source目录下的文件名和目录名
+    if replace.rename_paths {
+        let ReplaceRegex::Text(rules) = &regex else {
+            return Err(anyhow!("rename_paths can not be used in binary mode"));
+        };
+        return rename_paths(
+            Path::new(&replace.source),
+            &rules[0].0,
+            &replace.replacement,
+            options.dry_run,
+        );
+    }
+
+    // source为-时作为过滤器，从标准输入读取内容，替换后写入标准输出
+    if replace.source == STDIO_SOURCE {
+        return replace_stream(
+            replace,
+            &regex,
+            &mut io::stdin().lock(),
+            &mut io::stdout().lock(),
+            options.dry_run,
+        );
+    }
+
+    // 验证源路径glob是否有效
+    let path_list = glob(&replace.source)?;
+
+    // 把迭代器转换成Vec
+    let path_vec: Vec<_> = path_list.collect();
+    // 输出长度
+    // println!("路径列表的长度是: {}", path_vec.len());
+
+    if path_vec.is_empty() {
+        return Err(anyhow!("Invalid path. No files found."));
+    }
+
+    // 只处理匹配到的文件，跳过目录
+    let mut paths = Vec::new();
+    for entry in path_vec {
+        let path = entry.map_err(|e| anyhow!("Failed to read glob pattern. {}", e))?;
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+
+    // 性能统计：处理的文件数、字节数和替换次数
+    // 各文件并行处理，因此字节数和替换次数用原子变量累计
+    let start = options.profile.then(Instant::now);
+    let files = paths.len();
+    let bytes = AtomicUsize::new(0);
+    let replacements = AtomicUsize::new(0);
+
+    // 各文件之间互不影响，在线程池中并行读取、替换和写入，线程数由jobs指定，默认为CPU核心数
+    // 线程池中的线程使用与当前命令相同的日志级别
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or(0))
+        .build()?;
+    let log_level = command_log_level();
+    let failed: Vec<String> = pool.install(|| {
+        paths
+            .par_iter()
+            .filter_map(|path| {
+                with_log_level(log_level, || {
+                    replace_in_file(
+                        replace,
+                        &regex,
+                        encoding,
+                        path,
+                        options,
+                        &bytes,
+                        &replacements,
+                    )
+                })
+                .err()
+                .map(|e| format!("{}: {:#}", path.display(), e))
+            })
+            .collect()
+    });
+    let bytes = bytes.into_inner();
+    let replacements = replacements.into_inner();
+
+    if let Some(start) = start {
+        let total = start.elapsed();
+        options.record_profile(format!(
+            "Replace in {}: total {:.3?}, {} file(s), {} byte(s) read, {} replacement(s), {:.1} replacement(s)/s",
+            replace.source,
+            total,
+            files,
+            bytes,
+            replacements,
+            replacements as f64 / total.as_secs_f64().max(f64::EPSILON)
+        ));
+    }
+
+    // 汇总替换失败的文件
+    if !failed.is_empty() {
+        return Err(anyhow!(
+            "{} file(s) failed to replace:\n{}",
+            failed.len(),
+            failed.join("\n")
+        ));
+    }
+    Ok(())
+}
+
+// 预演replace时，以unified diff的格式输出文件的改动
+fn log_replace_diff(path: &Path, old: &str, new: &str) {
+    let diff = similar::TextDiff::from_lines(old, new);
+    let (mut deleted, mut inserted) = (0, 0);
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            similar::ChangeTag::Delete => deleted += 1,
+            similar::ChangeTag::Insert => inserted += 1,
+            similar::ChangeTag::Equal => {}
+        }
+    }
+    if deleted == 0 && inserted == 0 {
+        info!("- No changes in {}", path.display());
+        return;
+    }
+    let name = path.display().to_string();
+    info!(
+        "- Would change {} line(s) in {} (-{} +{}):\n{}",
+        deleted.max(inserted),
+        name,
+        deleted,
+        inserted,
+        diff.unified_diff().header(&name, &name)
+    );
+}
+
+// 表示标准输入输出的source
+const STDIO_SOURCE: &str = "-";
+
+// 读取input的全部内容，替换后写入output
+fn replace_stream(
+    replace: &Replace,
+    regex: &ReplaceRegex,
+    input: &mut impl Read,
+    output: &mut impl Write,
+    dry_run: bool,
+) -> Result<()> {
+    let mut content = Vec::new();
+    input.read_to_end(&mut content)?;
+    let replaced_content = match regex {
+        ReplaceRegex::Text(rules) => {
+            let text = String::from_utf8(content).context("stdin is not valid UTF-8")?;
+            let replaced = replace_text(rules, &text, replace.transform, None)?;
+            // 预演时只输出改动，不写入标准输出
+            if dry_run {
+                log_replace_diff(Path::new("<stdin>"), &text, &replaced);
+                return Ok(());
+            }
+            replaced.into_bytes()
+        }
+        ReplaceRegex::Binary(regex) => {
+            if dry_run {
+                info!(
+                    "- Would replace {} match(es) in <stdin>",
+                    regex.find_iter(&content).count()
+                );
+                return Ok(());
+            }
+            replace_bytes_fixed_length(regex, &content, &replace.replacement)?
+        }
+    };
+    output.write_all(&replaced_content)?;
+    output.flush()?;
+    Ok(())
+}
+
+// 对单个文件执行替换，profile为true时累计读取的字节数和替换次数
+fn replace_in_file(
+    replace: &Replace,
+    regex: &ReplaceRegex,
+    encoding: Option<&'static Encoding>,
+    path: &Path,
+    options: &ExecuteOptions,
+    bytes: &AtomicUsize,
+    replacements: &AtomicUsize,
+) -> Result<()> {
+    match regex {
+        ReplaceRegex::Text(rules) => {
+            // 读取文件内容并替换匹配的部分
+            let (file_content, bom) = match encoding {
+                Some(encoding) => read_encoded(path, encoding)?,
+                None => (fs::read_to_string(path)?, Vec::new()),
+            };
+            if options.profile {
+                bytes.fetch_add(file_content.len(), Ordering::Relaxed);
+            }
+            let replaced_content = replace_text(
+                rules,
+                &file_content,
+                replace.transform,
+                options.profile.then_some(replacements),
+            )?;
+            // 预演时只输出改动，不写入文件
+            if options.dry_run {
+                log_replace_diff(path, &file_content, &replaced_content);
+                return Ok(());
+            }
+            // 写入新的文件内容
+            match encoding {
+                Some(encoding) => fs::write(path, encode_text(&replaced_content, encoding, bom)?)?,
+                None => fs::write(path, replaced_content)?,
+            }
+        }
+        ReplaceRegex::Binary(regex) => {
+            let file_content = fs::read(path)?;
+            if options.profile {
+                bytes.fetch_add(file_content.len(), Ordering::Relaxed);
+                replacements.fetch_add(regex.find_iter(&file_content).count(), Ordering::Relaxed);
+            }
+            let replaced_content =
+                replace_bytes_fixed_length(regex, &file_content, &replace.replacement)?;
+            // 二进制文件无法按行比较，预演时只输出匹配的数量
+            if options.dry_run {
+                info!(
+                    "- Would replace {} match(es) in {}",
+                    regex.find_iter(&file_content).count(),
+                    path.display()
+                );
+                return Ok(());
+            }
+            fs::write(path, replaced_content)?;
+        }
+    }
+    Ok(())
+}
+
+// 按指定的编码解码命令的输出，为空时按UTF-8解码，无效的字节替换为U+FFFD
+fn decode_output<'a>(bytes: &'a [u8], encoding: Option<&'static Encoding>) -> Cow<'a, str> {
+    match encoding {
+        Some(encoding) => encoding.decode(bytes).0,
+        None => String::from_utf8_lossy(bytes),
+    }
+}
+
+// 根据名称查找文本编码，名称按WHATWG的标签匹配，不区分大小写
+fn text_encoding(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| anyhow!("Unknown encoding {}", label))
+}
+
+// 按指定的编码读取文本文件，返回解码后的文本和文件开头与该编码对应的BOM，写回时原样保留BOM
+// 含有该编码下无效的字节时返回错误，以免写回时损坏文件
+fn read_encoded(path: &Path, encoding: &'static Encoding) -> Result<(String, Vec<u8>)> {
+    let content = fs::read(path)?;
+    let bom = match Encoding::for_bom(&content) {
+        Some((bom_encoding, length)) if bom_encoding == encoding => content[..length].to_vec(),
+        _ => Vec::new(),
+    };
+    let (text, had_errors) = encoding.decode_without_bom_handling(&content[bom.len()..]);
+    if had_errors {
+        return Err(anyhow!("File is not valid {}", encoding.name()));
+    }
+    Ok((text.into_owned(), bom))
+}
+
+// 将文本按指定的编码编码，并在开头加上BOM
+// encoding_rs不支持编码为UTF-16，需要单独处理
+fn encode_text(text: &str, encoding: &'static Encoding, mut bom: Vec<u8>) -> Result<Vec<u8>> {
+    if encoding == UTF_16LE {
+        bom.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+    } else if encoding == UTF_16BE {
+        bom.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+    } else {
+        let (bytes, _, had_unmappable) = encoding.encode(text);
+        if had_unmappable {
+            return Err(anyhow!(
+                "Replaced text can not be represented in {}",
+                encoding.name()
+            ));
+        }
+        bom.extend_from_slice(&bytes);
+    }
+    Ok(bom)
+}
+
+// 按顺序应用各条替换规则，后面的规则作用于前面的规则替换后的内容
+// 传入replacements时累计各条规则的替换次数
+fn replace_text(
+    rules: &[(Regex, String)],
+    content: &str,
+    transform: Option<Transform>,
+    replacements: Option<&AtomicUsize>,
+) -> Result<String> {
+    let mut text = content.to_string();
+    for (regex, replacement) in rules {
+        if let Some(replacements) = replacements {
+            replacements.fetch_add(regex.find_iter(&text).count(), Ordering::Relaxed);
+        }
+        text = match transform {
+            Some(transform) => replace_transformed(regex, &text, replacement, transform)?,
+            None => regex.replace_all(&text, replacement.as_str()).into_owned(),
+        };
+    }
+    Ok(text)
+}
+
+// 替换所有匹配，每处匹配按replacement展开后再进行转换
+fn replace_transformed(
+    regex: &Regex,
+    content: &str,
+    replacement: &str,
+    transform: Transform,
+) -> Result<String> {
+    let mut error = None;
+    let replaced = regex.replace_all(content, |caps: &regex::Captures| {
+        let mut expanded = String::new();
+        caps.expand(replacement, &mut expanded);
+        transform.apply(&expanded).unwrap_or_else(|e| {
+            error.get_or_insert(e);
+            expanded
+        })
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(replaced.into_owned()),
+    }
+}
+
+// 将root目录下名称匹配正则的文件和目录重命名，root本身不会被重命名
+// 按路径深度从深到浅处理，保证重命名父目录时其下的路径已经处理完毕，不会失效
+fn rename_paths(root: &Path, regex: &Regex, replacement: &str, dry_run: bool) -> Result<()> {
+    if !root.is_dir() {
+        return Err(anyhow!("No such source directory"));
+    }
+
+    let mut paths = Vec::new();
+    for entry in WalkBuilder::new(root).standard_filters(false).build() {
+        let entry = entry?;
+        if entry.depth() > 0 {
+            paths.push(entry.into_path());
+        }
+    }
+    paths.sort_by_key(|path| std::cmp::Reverse(path.components().count()));
+
+    for path in paths {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let new_name = regex.replace_all(name, replacement);
+        if new_name == name {
+            continue;
+        }
+        let new_path = path.with_file_name(new_name.as_ref());
+        if new_path.exists() {
+            return Err(anyhow!(
+                "Can not rename {} to {}: target already exists",
+                path.display(),
+                new_path.display()
+            ));
+        }
+        if dry_run {
+            info!(
+                "- Would rename {} to {}",
+                path.display(),
+                new_path.display()
+            );
+            continue;
+        }
+        trace!("- Renaming {} to {}", path.display(), new_path.display());
+        fs::rename(&path, &new_path)?;
+    }
+    Ok(())
+}
+
+// replace命令使用的正则表达式，按文本或按字节匹配
+enum ReplaceRegex {
+    Text(Vec<(Regex, String)>), // 按顺序应用的正则和替换内容
+    Binary(BytesRegex),         // 二进制模式下按字节匹配的正则
+}
+
+// 按字节替换内容，每处替换后的长度必须与匹配到的长度相同
+// 替换后的内容较短时在末尾补\0，较长时返回错误，以保证文件中其他内容的偏移量不变
+fn replace_bytes_fixed_length(
+    regex: &BytesRegex,
+    content: &[u8],
+    replacement: &str,
+) -> Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(content.len());
+    let mut last_end = 0;
+    for caps in regex.captures_iter(content) {
+        let whole = caps.get(0).unwrap();
+        let mut expanded = Vec::new();
+        caps.expand(replacement.as_bytes(), &mut expanded);
+        if expanded.len() > whole.len() {
+            return Err(anyhow!(
+                "Replacement at offset {} is {} bytes, longer than the {} bytes matched",
+                whole.start(),
+                expanded.len(),
+                whole.len()
+            ));
+        }
+        expanded.resize(whole.len(), 0);
+
+        result.extend_from_slice(&content[last_end..whole.start()]);
+        result.extend_from_slice(&expanded);
+        last_end = whole.end();
+    }
+    result.extend_from_slice(&content[last_end..]);
+    Ok(result)
+}
+
+// 定义一个函数来执行run命令
+pub fn execute_run(run: &Run, options: &ExecuteOptions) -> Result<()> {
+    // 输出提示
+    info!("*** Running command: {}", run.command);
+
+    // 读取要写入标准输入的内容
+    let input = match (&run.stdin, &run.stdin_file) {
+        (Some(_), Some(_)) => {
+            return Err(anyhow!("Only one of stdin and stdin_file can be set"));
+        }
+        (Some(stdin), None) => Some(stdin.clone().into_bytes()),
+        (None, Some(stdin_file)) => Some(fs::read(stdin_file)?),
+        (None, None) => None,
+    };
+
+    // 用于判断失败后是否重试的正则
+    let retry_on = run.retry_on.as_deref().map(Regex::new).transpose()?;
+    let output_encoding = run
+        .output_encoding
+        .as_deref()
+        .map(text_encoding)
+        .transpose()?;
+    // 命令成功后，用于检查输出的正则
+    let expect_stdout = run.expect_stdout.as_deref().map(Regex::new).transpose()?;
+    let expect_stderr = run.expect_stderr.as_deref().map(Regex::new).transpose()?;
+    let ignore_stderr = run
+        .ignore_stderr_pattern
+        .as_deref()
+        .map(Regex::new)
+        .transpose()?;
+
+    // 输入文件和命令都未改变时，跳过命令
+    let cache_key = match &run.cache_key_files {
+        Some(_) if run.capture_exit_into.is_some() => {
+            return Err(anyhow!(
+                "cache_key_files cannot be used with capture_exit_into"
+            ));
+        }
+        Some(files) => {
+            let key = run_cache_key(&run.command, files)?;
+            if Path::new(RUN_CACHE_DIR).join(&key).is_file() {
+                info!("- Inputs unchanged, skipping (cache key {})", key);
+                return Ok(());
+            }
+            Some(key)
+        }
+        None => None,
+    };
+
+    let command = &run.command;
+    let words = shell_words::split(command).unwrap(); // 用shell-words库来分割字符串
+    let mut attempt = 0;
+    loop {
+        let child = spawn_child(
+            apply_env(
+                SysCommand::new(&words[0]) // words[0]是命令
+                    .args(&words[1..]), // words[1..]是参数组
+                run,
+            ),
+            input.is_some(),
+        ) // 启动命令
+        .or_else(|e| {
+            // 关闭了回退时，直接返回启动失败的原因
+            if !run.use_shell_fallback.unwrap_or(true) {
+                return Err(e);
+            }
+            // 如果失败了，就用cmd /c来执行
+            if cfg!(target_os = "windows") {
+                spawn_child(
+                    apply_env(SysCommand::new("cmd").arg("/C").args(&words), run),
+                    input.is_some(),
+                )
+            } else {
+                spawn_child(
+                    apply_env(SysCommand::new("sh").arg("-c").args(&words), run),
+                    input.is_some(),
+                )
+            }
+        })
+        .with_context(|| format!("failed to execute {}", words[0]))?;
+        // 等待命令结束并获取输出
+        let output = wait_child(child, input.as_deref(), &options.cancelled)?;
+        if let Some(stdout_file) = &run.stdout_file {
+            fs::write(stdout_file, &output.stdout)
+                .with_context(|| format!("Failed to write stdout to {}", stdout_file))?;
+        }
+        let max_output_bytes = run.max_output_bytes.or(options.max_output_bytes);
+        let stdout = decode_output(&output.stdout, output_encoding);
+        let stderr = decode_output(&output.stderr, output_encoding);
+
+        // 保存退出码，此时退出码非0不视为失败
+        if let Some(name) = &run.capture_exit_into {
+            let code = output
+                .status
+                .code()
+                .ok_or_else(|| anyhow!("command was terminated by a signal"))?;
+            info!("- Captured exit code {} into {}", code, name);
+            options.capture(name, code.to_string());
+            if !output.status.success() {
+                return Ok(());
+            }
+        }
+
+        // 检查命令是否成功
+        if output.status.success() {
+            // 输出标准输出和标准错误
+            info!("- result: {}", truncate_output(&stdout, max_output_bytes));
+            //println!("stderr: {}", String::from_utf8_lossy(&output.stderr));
+
+            // 检查输出是否符合预期
+            if let Some(expect_stdout) = &expect_stdout {
+                if !expect_stdout.is_match(&stdout) {
+                    return Err(anyhow!(
+                        "stdout does not match expected pattern \"{}\"",
+                        expect_stdout
+                    ));
+                }
+            }
+            if let Some(expect_stderr) = &expect_stderr {
+                if !expect_stderr.is_match(&stderr) {
+                    return Err(anyhow!(
+                        "stderr does not match expected pattern \"{}\"",
+                        expect_stderr
+                    ));
+                }
+            }
+            // 检查stderr中是否有未被忽略的输出
+            if run.fail_on_stderr {
+                let unexpected = stderr.lines().find(|line| {
+                    !line.trim().is_empty()
+                        && !ignore_stderr
+                            .as_ref()
+                            .is_some_and(|ignore| ignore.is_match(line))
+                });
+                if let Some(line) = unexpected {
+                    return Err(anyhow!(
+                        "command succeeded but wrote to stderr: \"{}\"",
+                        line
+                    ));
+                }
+            }
+            // 记录缓存键，下次输入未改变时跳过
+            if let Some(key) = &cache_key {
+                fs::create_dir_all(RUN_CACHE_DIR)?;
+                fs::write(Path::new(RUN_CACHE_DIR).join(key), &run.command)?;
+            }
+            return Ok(());
+        }
+
+        error!("stderr: {}", truncate_output(&stderr, max_output_bytes));
+        if attempt >= run.retries {
+            // 返回错误值
+            return Err(anyhow!("command failed with status: {}", output.status));
+        }
+
+        // 判断是否需要重试
+        attempt += 1;
+        match &retry_on {
+            Some(retry_on) if !retry_on.is_match(&stderr) => {
+                warn!(
+                    "- Not retrying: stderr does not match retry_on pattern \"{}\"",
+                    retry_on
+                );
+                return Err(anyhow!("command failed with status: {}", output.status));
+            }
+            Some(retry_on) => info!(
+                "- stderr matches retry_on pattern \"{}\", retrying ({}/{})",
+                retry_on, attempt, run.retries
+            ),
+            None => info!("- Retrying ({}/{})", attempt, run.retries),
+        }
+    }
+}
+
+// 保存run命令缓存键的目录，相对于工作路径
+pub const RUN_CACHE_DIR: &str = ".autopackager-cache";
+
+// 计算run命令的缓存键，即命令字符串和各输入文件的路径及内容的sha256
+// 通配符匹配到的文件按路径排序，保证结果与遍历顺序无关
+fn run_cache_key(command: &str, files: &[String]) -> Result<String> {
+    let mut paths = Vec::new();
+    for pattern in files {
+        if is_glob_pattern(pattern) {
+            paths.extend(glob(pattern)?.collect::<Result<Vec<_>, _>>()?);
+        } else {
+            paths.push(PathBuf::from(pattern));
+        }
+    }
+    paths.sort();
+
+    let mut hasher = Sha256::new();
+    hasher.update(command.as_bytes());
+    for path in paths.iter().filter(|path| !path.is_dir()) {
+        let content_hash = file_sha256(path)
+            .with_context(|| format!("Failed to read cache key file {}", path.display()))?;
+        hasher.update([0]);
+        hasher.update(path.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(content_hash);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+// 截断要输出到日志的命令输出，超过max_bytes时只保留开头的部分，并注明截断的字节数
+fn truncate_output(text: &str, max_bytes: Option<usize>) -> Cow<'_, str> {
+    match max_bytes {
+        Some(max_bytes) if text.len() > max_bytes => {
+            // 在字符边界处截断，避免截断多字节字符
+            let end = (0..=max_bytes)
+                .rev()
+                .find(|index| text.is_char_boundary(*index))
+                .unwrap_or(0);
+            Cow::Owned(format!(
+                "{}...[truncated {} bytes]",
+                &text[..end],
+                text.len() - end
+            ))
+        }
+        _ => Cow::Borrowed(text),
+    }
+}
+
+// 定义一个函数来执行修改结构化文件中的值的命令
+// 只修改目标键的值，文件中的注释、排版和其他值的类型保持不变
+pub fn execute_set_value(set_value: &SetValue) -> Result<()> {
+    // 输出提示
+    info!(
+        "*** Setting {} to \"{}\" in {}",
+        set_value.key_path, set_value.value, set_value.path
+    );
+
+    let path = Path::new(&set_value.path);
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let keys: Vec<&str> = set_value.key_path.split('.').collect();
+    let mut output = match ConfigFormat::from_path(path) {
+        ConfigFormat::Yaml => set_yaml_value(&content, &keys, &set_value.value)
+            .with_context(|| format!("Failed to set {}", set_value.key_path))?,
+        ConfigFormat::Json => set_json_value(&content, &set_value.key_path, &set_value.value)?,
+        ConfigFormat::Toml => {
+            let mut document: toml_edit::DocumentMut = content.parse()?;
+            set_toml_item(document.as_item_mut(), &keys, &set_value.value)
+                .with_context(|| format!("Failed to set {}", set_value.key_path))?;
+            document.to_string()
+        }
+    };
+    // 保持原文件末尾的换行
+    if content.ends_with('\n') && !output.ends_with('\n') {
+        output.push('\n');
+    }
+    fs::write(path, output)?;
+    Ok(())
+}
+
+// 按.分隔的键路径设置值，不存在的对象键会被创建，数组的下标必须已存在
+fn set_key_path(
+    root: &mut serde_json::Value,
+    key_path: &str,
+    value: serde_json::Value,
+) -> Result<()> {
+    let mut current = root;
+    for key in key_path.split('.') {
+        current = match current {
+            serde_json::Value::Object(object) => object
+                .entry(key)
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new())),
+            serde_json::Value::Array(array) => key
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| array.get_mut(index))
+                .ok_or_else(|| anyhow!("No element {} in {}", key, key_path))?,
+            _ => {
+                return Err(anyhow!(
+                    "Cannot find {} in {}: not an object",
+                    key,
+                    key_path
+                ))
+            }
+        };
+    }
+    *current = value;
+    Ok(())
+}
+
+// 修改json文件中的值，返回新的文件内容
+// 目标键已存在时直接替换原文中的值，其余部分逐字节保持不变，否则重新格式化整个文件
+fn set_json_value(content: &str, key_path: &str, value: &str) -> Result<String> {
+    let mut root: serde_json::Value = serde_json::from_str(content)?;
+    let value = serde_json::Value::String(value.to_string());
+    set_key_path(&mut root, key_path, value.clone())?;
+
+    let keys: Vec<&str> = key_path.split('.').collect();
+    let mut position = 0;
+    if let Some((start, end)) = json_value_span(content.as_bytes(), &mut position, &keys) {
+        let edited = format!(
+            "{}{}{}",
+            &content[..start],
+            serde_json::to_string(&value)?,
+            &content[end..]
+        );
+        if serde_json::from_str::<serde_json::Value>(&edited)
+            .ok()
+            .as_ref()
+            == Some(&root)
+        {
+            return Ok(edited);
+        }
+    }
+    Ok(serde_json::to_string_pretty(&root)?)
+}
+
+// 从position开始扫描json原文中的一个值，返回其中键路径对应的值的起止位置，找不到时返回None
+fn json_value_span(bytes: &[u8], position: &mut usize, keys: &[&str]) -> Option<(usize, usize)> {
+    skip_json_whitespace(bytes, position);
+    let Some((key, rest)) = keys.split_first() else {
+        let start = *position;
+        skip_json_value(bytes, position)?;
+        return Some((start, *position));
+    };
+    match bytes.get(*position)? {
+        b'{' => {
+            *position += 1;
+            loop {
+                skip_json_whitespace(bytes, position);
+                let start = *position;
+                skip_json_value(bytes, position)?;
+                let name: String = serde_json::from_slice(&bytes[start..*position]).ok()?;
+                skip_json_whitespace(bytes, position);
+                if bytes.get(*position) != Some(&b':') {
+                    return None;
+                }
+                *position += 1;
+                if name == *key {
+                    return json_value_span(bytes, position, rest);
+                }
+                skip_json_whitespace(bytes, position);
+                skip_json_value(bytes, position)?;
+                skip_json_whitespace(bytes, position);
+                if bytes.get(*position) != Some(&b',') {
+                    return None;
+                }
+                *position += 1;
+            }
+        }
+        b'[' => {
+            let index = key.parse::<usize>().ok()?;
+            *position += 1;
+            for _ in 0..index {
+                skip_json_whitespace(bytes, position);
+                skip_json_value(bytes, position)?;
+                skip_json_whitespace(bytes, position);
+                if bytes.get(*position) != Some(&b',') {
+                    return None;
+                }
+                *position += 1;
+            }
+            json_value_span(bytes, position, rest)
+        }
+        _ => None,
+    }
+}
+
+fn skip_json_whitespace(bytes: &[u8], position: &mut usize) {
+    while bytes
+        .get(*position)
+        .is_some_and(|byte| byte.is_ascii_whitespace())
+    {
+        *position += 1;
+    }
+}
+
+// 跳过一个完整的json值：字符串、对象、数组或数字等字面量
+fn skip_json_value(bytes: &[u8], position: &mut usize) -> Option<()> {
+    match bytes.get(*position)? {
+        b'"' => {
+            *position += 1;
+            loop {
+                match bytes.get(*position)? {
+                    b'\\' => *position += 2,
+                    b'"' => break,
+                    _ => *position += 1,
+                }
+            }
+            *position += 1;
+        }
+        b'{' | b'[' => {
+            // 对象和数组按括号的嵌套层数跳过，其中的字符串单独跳过，以免误判字符串中的括号
+            let mut depth = 0;
+            loop {
+                match bytes.get(*position)? {
+                    b'"' => {
+                        skip_json_value(bytes, position)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            *position += 1;
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                *position += 1;
+            }
+        }
+        _ => {
+            let start = *position;
+            while bytes.get(*position).is_some_and(|byte| {
+                !matches!(byte, b',' | b'}' | b']') && !byte.is_ascii_whitespace()
+            }) {
+                *position += 1;
+            }
+            if *position == start {
+                return None;
+            }
+        }
+    }
+    Some(())
+}
+
+// 修改yaml文件中的值，返回新的文件内容
+// 目标键已存在并且是单行的值时直接替换原文中的值，其他情况只能重新序列化整个文件，注释会丢失
+fn set_yaml_value(content: &str, keys: &[&str], value: &str) -> Result<String> {
+    let mut root: serde_yaml::Value = serde_yaml::from_str(content)?;
+    let mut current = &mut root;
+    for (depth, key) in keys.iter().enumerate() {
+        current = match current {
+            serde_yaml::Value::Mapping(mapping) => mapping
+                .entry(serde_yaml::Value::String(key.to_string()))
+                .or_insert_with(|| serde_yaml::Value::Mapping(serde_yaml::Mapping::new())),
+            serde_yaml::Value::Sequence(sequence) => key
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| sequence.get_mut(index))
+                .ok_or_else(|| anyhow!("No element {} in {}", key, keys[..=depth].join(".")))?,
+            _ => return Err(anyhow!("Cannot find {}: not an object", key)),
+        };
+    }
+    *current = serde_yaml::Value::String(value.to_string());
+
+    // 替换之后重新解析，结果与修改后的值一致才说明原文中的替换是正确的
+    let scalar = serde_yaml::to_string(&serde_yaml::Value::String(value.to_string()))?;
+    if let Some(edited) = replace_yaml_scalar(content, keys, scalar.trim_end()) {
+        if serde_yaml::from_str::<serde_yaml::Value>(&edited)
+            .ok()
+            .as_ref()
+            == Some(&root)
+        {
+            return Ok(edited);
+        }
+    }
+    if content
+        .lines()
+        .any(|line| line.trim_start().starts_with('#'))
+    {
+        warn!("- Rewriting the whole file, comments are not preserved");
+    }
+    Ok(serde_yaml::to_string(&root)?)
+}
+
+// 在yaml原文中找到键路径对应的行，将其中的单行值替换为scalar，保留行尾的注释
+// 只识别块格式的映射，找不到时返回None
+fn replace_yaml_scalar(content: &str, keys: &[&str], scalar: &str) -> Option<String> {
+    // 当前行所在的各层映射的缩进和键
+    let mut parents: Vec<(usize, &str)> = Vec::new();
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if trimmed.trim().is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let Some((key, rest)) = trimmed.split_once(':') else {
+            continue;
+        };
+        if !(rest.is_empty() || rest.starts_with([' ', '\t', '\r', '\n'])) {
+            continue;
+        }
+        while parents.last().is_some_and(|(parent, _)| *parent >= indent) {
+            parents.pop();
+        }
+        parents.push((indent, key.trim_end()));
+        if !parents.iter().map(|(_, key)| key).eq(keys.iter()) {
+            continue;
+        }
+
+        // 值在冒号之后，到行尾的注释或换行为止
+        let body = rest.trim_end_matches(['\r', '\n']);
+        let value_start = body.len() - body.trim_start().len();
+        let value_end = body.find(" #").unwrap_or(body.len());
+        let old = body.get(value_start..value_end)?.trim_end();
+        if old.is_empty() || old.starts_with(['|', '>', '&', '*', '!', '{', '[']) {
+            return None;
+        }
+        let start = line_start + indent + key.len() + 1 + value_start;
+        return Some(format!(
+            "{}{}{}",
+            &content[..start],
+            scalar,
+            &content[start + old.len()..]
+        ));
+    }
+    None
+}
+
+// 按键路径修改toml文档中的值，不存在的表会被创建，数组的下标必须已存在
+// 替换已有的值时保留它前后的空白和注释
+fn set_toml_item(item: &mut toml_edit::Item, keys: &[&str], value: &str) -> Result<()> {
+    let Some((key, rest)) = keys.split_first() else {
+        match item {
+            toml_edit::Item::Value(old) => {
+                let decor = old.decor().clone();
+                *old = toml_edit::Value::from(value);
+                *old.decor_mut() = decor;
+            }
+            _ => *item = toml_edit::value(value),
+        }
+        return Ok(());
+    };
+    match item {
+        toml_edit::Item::Table(table) => {
+            let child = table.entry(key).or_insert_with(|| {
+                // 中间层的表只在其中有值时才输出表头
+                let mut table = toml_edit::Table::new();
+                table.set_implicit(true);
+                toml_edit::Item::Table(table)
+            });
+            set_toml_item(child, rest, value)
+        }
+        toml_edit::Item::ArrayOfTables(array) => {
+            let table = key
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| array.get_mut(index))
+                .ok_or_else(|| anyhow!("No element {}", key))?;
+            if rest.is_empty() {
+                return Err(anyhow!("Cannot replace table {} with a value", key));
+            }
+            let mut child = toml_edit::Item::Table(std::mem::take(table));
+            let result = set_toml_item(&mut child, rest, value);
+            if let toml_edit::Item::Table(child) = child {
+                *table = child;
+            }
+            result
+        }
+        toml_edit::Item::Value(inner) => set_toml_value(inner, keys, value),
+        toml_edit::Item::None => Err(anyhow!("Cannot find {}: not a table", key)),
+    }
+}
+
+// 修改toml中内联的表和数组里的值
+fn set_toml_value(item: &mut toml_edit::Value, keys: &[&str], value: &str) -> Result<()> {
+    let Some((key, rest)) = keys.split_first() else {
+        let decor = item.decor().clone();
+        *item = toml_edit::Value::from(value);
+        *item.decor_mut() = decor;
+        return Ok(());
+    };
+    let child = match item {
+        toml_edit::Value::InlineTable(table) => table
+            .entry(*key)
+            .or_insert_with(|| toml_edit::Value::InlineTable(toml_edit::InlineTable::new())),
+        toml_edit::Value::Array(array) => key
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| array.get_mut(index))
+            .ok_or_else(|| anyhow!("No element {}", key))?,
+        _ => return Err(anyhow!("Cannot find {}: not a table", key)),
+    };
+    set_toml_value(child, rest, value)
+}
+
+// 定义一个函数来执行校验和检查命令
+// 检查完所有列出的文件后，一次性报告所有不匹配和缺失的文件
+pub fn execute_verify_checksums(verify: &VerifyChecksums) -> Result<()> {
+    // 输出提示
+    info!(
+        "*** Verifying files in {} against {}",
+        verify.base_dir, verify.checksums_file
+    );
+
+    let content = fs::read_to_string(&verify.checksums_file)
+        .with_context(|| format!("Failed to read {}", verify.checksums_file))?;
+    let mut checked = 0;
+    let mut failures = Vec::new();
+    for (line_number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (expected, file_name) = parse_checksum_line(line).ok_or_else(|| {
+            anyhow!(
+                "Invalid line {} in {}: {}",
+                line_number + 1,
+                verify.checksums_file,
+                line
+            )
+        })?;
+
+        let path = Path::new(&verify.base_dir).join(file_name);
+        checked += 1;
+        match file_sha256(&path) {
+            Ok(actual) if to_hex(&actual).eq_ignore_ascii_case(expected) => {
+                trace!("- {}: OK", file_name);
+            }
+            Ok(_) => {
+                error!("- {}: checksum mismatch", file_name);
+                failures.push(file_name);
+            }
+            Err(e) => {
+                error!("- {}: {:#}", file_name, e);
+                failures.push(file_name);
+            }
+        }
+    }
+
+    info!("- Checked {} file(s), {} failed", checked, failures.len());
+    if !failures.is_empty() {
+        return Err(anyhow!(
+            "{} of {} file(s) failed verification: {}",
+            failures.len(),
+            checked,
+            failures.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+// 定义一个函数来执行前置条件检查命令
+pub fn execute_assert(assert: &Assert) -> Result<()> {
+    // 输出提示
+    info!("*** Checking {}", assert.path);
+    if assert.is_file && assert.is_dir {
+        return Err(anyhow!("is_file and is_dir cannot be used together"));
+    }
+
+    // 包含通配符时检查所有匹配到的路径，否则只检查该路径本身
+    let matched: Vec<PathBuf> = if is_glob_pattern(&assert.path) {
+        glob(&assert.path)?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|path| assert.matches(path))
+            .collect()
+    } else {
+        Some(PathBuf::from(&assert.path))
+            .filter(|path| assert.matches(path))
+            .into_iter()
+            .collect()
+    };
+
+    match (assert.exists, matched.is_empty()) {
+        (true, true) => Err(anyhow!(
+            "Assertion failed: expected {} {} to exist",
+            assert.kind_name(),
+            assert.path
+        )),
+        (false, false) => Err(anyhow!(
+            "Assertion failed: expected {} {} not to exist, but found {}",
+            assert.kind_name(),
+            assert.path,
+            matched.iter().map(|path| path.display()).join(", ")
+        )),
+        _ => {
+            trace!("- Assertion passed");
+            Ok(())
+        }
+    }
+}
+
+// 解析sha256sum格式的一行，返回校验和与文件路径
+// 路径前可以有表示二进制模式的*
+fn parse_checksum_line(line: &str) -> Option<(&str, &str)> {
+    let (hash, rest) = line.split_once(char::is_whitespace)?;
+    let file_name = rest.strip_prefix([' ', '*']).unwrap_or(rest);
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) || file_name.is_empty() {
+        return None;
+    }
+    Some((hash, file_name))
+}
+
+// 将字节转换为小写的十六进制字符串
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// 定义一个函数来执行自定义命令
+pub fn execute_custom(custom: &Custom, options: &ExecuteOptions) -> Result<()> {
+    // 输出提示
+    info!("*** Running custom handler: {}", custom.handler);
+
+    let mut command = SysCommand::new(&custom.handler);
+    for (key, value) in &custom.args {
+        command.env(custom_arg_env_name(key), value);
+    }
+    let input = serde_json::to_vec(&custom.args)?;
+    let child = spawn_child(&mut command, true)
+        .with_context(|| format!("failed to start handler {}", custom.handler))?;
+    let output = wait_child(child, Some(&input), &options.cancelled)?;
+
+    let max_output_bytes = options.max_output_bytes;
+    info!(
+        "- result: {}",
+        truncate_output(&String::from_utf8_lossy(&output.stdout), max_output_bytes)
+    );
+    if !output.status.success() {
+        error!(
+            "stderr: {}",
+            truncate_output(&String::from_utf8_lossy(&output.stderr), max_output_bytes)
+        );
+        return Err(anyhow!("handler failed with status: {}", output.status));
+    }
+    Ok(())
+}
+
+// 将自定义命令的参数名转换为环境变量名，非字母数字的字符替换为下划线
+fn custom_arg_env_name(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("AUTOPACKAGER_ARG_{}", key)
+}
+
+// 按照run的设置清空子进程的环境变量，并保留keep_env中列出的变量，再设置env中的变量，同时设置子进程的工作路径
+fn apply_env<'a>(command: &'a mut SysCommand, run: &Run) -> &'a mut SysCommand {
+    if let Some(cwd) = &run.cwd {
+        command.current_dir(cwd);
+    }
+    if run.clear_env {
+        command.env_clear();
+        for name in &run.keep_env {
+            if let Some(value) = env::var_os(name) {
+                command.env(name, value);
+            }
+        }
+    }
+    command.envs(&run.env)
+}
+
+// 启动命令，捕获其标准输出和标准错误；has_input为true时通过管道写入标准输入
+fn spawn_child(command: &mut SysCommand, has_input: bool) -> io::Result<Child> {
+    command
+        .stdin(if has_input {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+}
+
+// 等待命令结束并获取输出，如果有input则写入到命令的标准输入
+// 等待期间收到取消请求时，结束子进程并返回错误
+fn wait_child(mut child: Child, input: Option<&[u8]>, cancelled: &AtomicBool) -> Result<Output> {
+    // 在单独的线程中写入标准输入和读取输出，避免子进程输出过多时互相阻塞
+    // 写入结束后stdin被drop，子进程会读到EOF
+    let writer = child.stdin.take().zip(input).map(|(mut stdin, input)| {
+        let input = input.to_vec();
+        thread::spawn(move || stdin.write_all(&input))
+    });
+    let stdout_reader = child.stdout.take().map(|mut stdout| {
+        thread::spawn(move || {
+            let mut buffer = Vec::new();
+            stdout.read_to_end(&mut buffer).map(|_| buffer)
+        })
+    });
+    let stderr_reader = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut buffer = Vec::new();
+            stderr.read_to_end(&mut buffer).map(|_| buffer)
+        })
+    });
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if cancelled.load(Ordering::SeqCst) {
+            warn!("- Killing child process {}", child.id());
+            child.kill()?;
+            child.wait()?;
+            return Err(anyhow!("Cancelled"));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    if let Some(writer) = writer {
+        writer
+            .join()
+            .map_err(|_| anyhow!("Failed to write stdin"))?
+            .or_else(|e| match e.kind() {
+                // 子进程没有读取全部输入就退出时，忽略写入失败
+                io::ErrorKind::BrokenPipe => Ok(()),
+                _ => Err(e),
+            })?;
+    }
+    let read_output = |reader: Option<thread::JoinHandle<io::Result<Vec<u8>>>>| -> Result<Vec<u8>> {
+        match reader {
+            Some(reader) => Ok(reader
+                .join()
+                .map_err(|_| anyhow!("Failed to read output"))??),
+            None => Ok(Vec::new()),
+        }
+    };
+    Ok(Output {
+        status,
+        stdout: read_output(stdout_reader)?,
+        stderr: read_output(stderr_reader)?,
+    })
+}
+
+// 定义一个结构体，存储执行命令列表时的选项
+#[derive(Debug, Default)]
+pub struct ExecuteOptions {
+    // 失败的命令数量达到该值时停止执行剩余的命令，为空表示不限制
+    pub max_failures: Option<u64>,
+    // 对所有copy命令启用增量模式
+    pub incremental: bool,
+    // 取消标志，被设置后停止执行剩余的命令，并结束正在运行的子进程
+    pub cancelled: Arc<AtomicBool>,
+    // 是否统计copy和replace命令内部的性能数据
+    pub profile: bool,
+    // 统计到的性能数据，每条命令一行
+    pub profile_report: Mutex<Vec<String>>,
+    // 遇到第一个错误时立即中止，包括copy命令中单个文件的复制失败
+    pub fail_fast: bool,
+    // 预演模式，replace只输出将要进行的改动，其他命令跳过不执行
+    pub dry_run: bool,
+    // 变量名到值的映射，用于copy命令展开文件内容中的变量
+    pub variables: HashMap<String, String>,
+    // 展开文件内容时，引用的环境变量不存在是否报错
+    pub strict_env: bool,
+    // 执行过程中由命令捕获的变量，之后的命令执行前会展开对它们的引用
+    pub captured: Mutex<HashMap<String, String>>,
+    // 对所有copy命令生效的排除规则
+    pub global_exclude: Vec<String>,
+    // 并行处理文件时使用的线程数，为空表示使用CPU核心数
+    pub jobs: Option<usize>,
+    // 从该序号的命令开始执行，跳过之前的命令
+    pub continue_from: usize,
+    // 日志中最多输出的命令stdout和stderr字节数，为空表示不限制
+    pub max_output_bytes: Option<usize>,
+    // 对所有copy命令允许源路径不存在
+    pub allow_missing_source: bool,
+    // 对没有设置min_free_bytes的copy命令检查的最小可用空间
+    pub min_free_bytes: Option<u64>,
+    // 对每一组变量分别执行一遍全部命令，为空表示只执行一遍
+    pub matrix: Vec<BTreeMap<String, String>>,
+    // 实际执行的命令数量，不包括按序号跳过的命令，matrix中每一遍分别计数
+    pub executed: AtomicUsize,
+}
+
+impl ExecuteOptions {
+    // 保存一个捕获的变量
+    fn capture(&self, name: &str, value: String) {
+        if let Ok(mut captured) = self.captured.lock() {
+            captured.insert(name.to_string(), value);
+        }
+    }
+
+    // 返回目前捕获的所有变量
+    fn captured_variables(&self) -> HashMap<String, String> {
+        self.captured
+            .lock()
+            .map(|captured| captured.clone())
+            .unwrap_or_default()
+    }
+
+    // 记录一条性能统计数据
+    fn record_profile(&self, line: String) {
+        if let Ok(mut report) = self.profile_report.lock() {
+            report.push(line);
+        }
+    }
+}
+
+// 定义一个函数来执行命令组
+pub fn execute_group(group: &Group, options: &ExecuteOptions) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        // 输出提示
+        info!(
+            "*** Running group of {} command(s), attempt {}/{}",
+            group.commands.len(),
+            attempt + 1,
+            group.retries + 1
+        );
+        let result = group
+            .commands
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, command)| {
+                execute_with_captured(command, options)
+                    .with_context(|| format!("group command #{} ({})", index, command.kind()))
+            });
+        let error = match result {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= group.retries || options.cancelled.load(Ordering::SeqCst) => {
+                return Err(e)
+            }
+            Err(e) => e,
+        };
+
+        attempt += 1;
+        warn!(
+            "- Group failed: {:#}, retrying in {} second(s) ({}/{})",
+            error, group.delay_secs, attempt, group.retries
+        );
+        thread::sleep(Duration::from_secs(group.delay_secs));
+    }
+}
+
+// 定义一个函数来执行单条命令
+pub fn execute_command(command: &Command, options: &ExecuteOptions) -> Result<()> {
+    // 预演模式下只有replace（以及命令组中的replace）会执行，并且不修改文件，list不修改文件，同样会执行
+    if options.dry_run
+        && !matches!(
+            command,
+            Command::Replace(_) | Command::Group(_) | Command::List(_)
+        )
+    {
+        info!("*** Dry run, skipping: {}", command.describe());
+        return Ok(());
+    }
+    match command {
+        Command::Copy(copy) => execute_copy(copy, options),
+        Command::Replace(replace) => execute_replace(replace, options),
+        Command::Run(run) => execute_run(run, options),
+        Command::Custom(custom) => execute_custom(custom, options),
+        Command::VerifyChecksums(verify) => execute_verify_checksums(verify),
+        Command::Group(group) => execute_group(group, options),
+        Command::Assert(assert) => execute_assert(assert),
+        Command::SetValue(set_value) => execute_set_value(set_value),
+        Command::List(list) => execute_list(list, options),
+        Command::ManifestVar(manifest) => execute_manifest_var(manifest, options),
+        Command::GenerateIndex(index) => execute_generate_index(index),
+    }
+}
+
+// 先展开命令中对捕获变量的引用，并去掉解析时为它们保留的转义，再执行命令
+fn execute_with_captured(command: &Command, options: &ExecuteOptions) -> Result<()> {
+    substitute_captured(command, &options.captured_variables())
+        .and_then(|command| execute_command(&command, options))
+}
+
+// 定义一个函数来执行命令列表
+// 出错时返回每个出错命令的序号及原因
+pub fn execute_commands(
+    commands: &[CommandItem],
+    options: &ExecuteOptions,
+) -> Result<(), Vec<(usize, anyhow::Error)>> {
+    let mut errors = Vec::new();
+    if options.matrix.is_empty() {
+        execute_command_list(commands, options, &mut errors);
+    }
+    for (index, entry) in options.matrix.iter().enumerate() {
+        if options.cancelled.load(Ordering::SeqCst) {
+            break;
+        }
+        let description = entry
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .join(", ");
+        info!("*** Running matrix entry #{} ({})", index, description);
+        // 每组变量独立执行，之前捕获的变量不再保留，matrix中的变量作为捕获的变量在执行时展开
+        if let Ok(mut captured) = options.captured.lock() {
+            *captured = entry.clone().into_iter().collect();
+        }
+        let mut entry_errors = Vec::new();
+        execute_command_list(commands, options, &mut entry_errors);
+        errors.extend(
+            entry_errors
+                .into_iter()
+                .map(|(command, e)| (command, e.context(format!("matrix entry #{}", index)))),
+        );
+        // 失败次数达到上限时，跳过剩余的组
+        if options
+            .max_failures
+            .is_some_and(|max_failures| errors.len() as u64 >= max_failures)
+        {
+            break;
+        }
+    }
+    // 检查错误集合是否为空
+    if errors.is_empty() {
+        // 如果没有错误，就返回Ok(())
+        Ok(())
+    } else {
+        // 如果有错误，就返回Err(errors)
+        Err(errors)
+    }
+}
+
+// 返回从index开始的一批命令的结束位置，属于同一并行组的连续命令为一批，其余的命令各自为一批
+fn parallel_batch_end(commands: &[CommandItem], index: usize) -> usize {
+    match commands[index].parallel_group.as_deref() {
+        Some(group) => {
+            index
+                + commands[index..]
+                    .iter()
+                    .take_while(|item| item.parallel_group.as_deref() == Some(group))
+                    .count()
+        }
+        None => index + 1,
+    }
+}
+
+// 将命令列表按执行时的批次划分，每一批为一个序号范围
+fn parallel_batches(commands: &[CommandItem]) -> Vec<std::ops::Range<usize>> {
+    let mut batches = Vec::new();
+    let mut index = 0;
+    while index < commands.len() {
+        let end = parallel_batch_end(commands, index);
+        batches.push(index..end);
+        index = end;
+    }
+    batches
+}
+
+// 一条命令在执行计划中的说明，包括序号、阶段、order和是否可选
+fn plan_step(index: usize, item: &CommandItem) -> String {
+    let mut step = format!("Step #{}", index);
+    if let Some(stage) = &item.stage {
+        step.push_str(&format!(" [{}]", stage));
+    }
+    if let Some(order) = item.order {
+        step.push_str(&format!(" (order {})", order));
+    }
+    if item.optional {
+        step.push_str(" (optional)");
+    }
+    format!("{}: {}", step, item.command.describe())
+}
+
+// 以文本形式输出执行计划，同一批并行执行的命令缩进列在并行组名之下
+pub fn plan_text(commands: &[CommandItem]) -> String {
+    let mut text = String::new();
+    for batch in parallel_batches(commands) {
+        match commands[batch.start].parallel_group.as_deref() {
+            Some(group) if batch.len() > 1 => {
+                text.push_str(&format!("Parallel group {}:\n", group));
+                for index in batch {
+                    text.push_str(&format!("  {}\n", plan_step(index, &commands[index])));
+                }
+            }
+            _ => text.push_str(&format!(
+                "{}\n",
+                plan_step(batch.start, &commands[batch.start])
+            )),
+        }
+    }
+    text
+}
+
+// 以Graphviz DOT格式输出执行计划
+// 每一批中的命令都在上一批的所有命令完成之后执行，并行执行的命令放在同一个子图中
+pub fn plan_dot(commands: &[CommandItem]) -> String {
+    let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut dot = String::from("digraph plan {\n    node [shape=box];\n");
+    let mut previous: Option<std::ops::Range<usize>> = None;
+    for batch in parallel_batches(commands) {
+        for index in batch.clone() {
+            dot.push_str(&format!(
+                "    n{} [label={}];\n",
+                index,
+                quote(&plan_step(index, &commands[index]))
+            ));
+        }
+        if let Some(group) = commands[batch.start].parallel_group.as_deref() {
+            if batch.len() > 1 {
+                dot.push_str(&format!(
+                    "    subgraph cluster_{} {{\n        label={};\n        {};\n    }}\n",
+                    batch.start,
+                    quote(&format!("parallel group {}", group)),
+                    batch.clone().map(|index| format!("n{}", index)).join("; ")
+                ));
+            }
+        }
+        for from in previous.iter().flat_map(|previous| previous.clone()) {
+            for to in batch.clone() {
+                dot.push_str(&format!("    n{} -> n{};\n", from, to));
+            }
+        }
+        previous = Some(batch);
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+thread_local! {
+    // 当前线程上正在执行的命令设置的日志级别，为空时使用全局的级别
+    static COMMAND_LOG_LEVEL: Cell<Option<LevelFilter>> = const { Cell::new(None) };
+}
+
+// 是否有命令设置了log_level，没有时控制台日志的过滤器可以按全局的级别缓存每处日志的结果
+static COMMAND_LOG_LEVEL_USED: AtomicBool = AtomicBool::new(false);
+
+// 返回当前线程上正在执行的命令设置的日志级别，供控制台日志的过滤器使用
+// 并行组中的命令各自在单独的线程中执行，因此互不影响；replace在线程池中处理文件时会传入命令的级别
+fn command_log_level() -> Option<LevelFilter> {
+    COMMAND_LOG_LEVEL.with(Cell::get)
+}
+
+// 在执行f期间使用指定的日志级别，结束后恢复之前的级别
+fn with_log_level<T>(level: Option<LevelFilter>, f: impl FnOnce() -> T) -> T {
+    let previous = COMMAND_LOG_LEVEL.with(|cell| cell.replace(level.or(cell.get())));
+    let result = f();
+    COMMAND_LOG_LEVEL.with(|cell| cell.set(previous));
+    result
+}
+
+// 控制台日志的过滤器，执行设置了log_level的命令期间使用命令的级别，否则使用全局的级别
+// 有命令设置了log_level时，同一处日志在不同的命令中可能启用也可能不启用，因此不能缓存每处日志的结果，
+// 每条日志都重新判断；没有时按全局的级别缓存，避免对每条不输出的日志（包括依赖库中的）都进行判断
+pub struct CommandLogFilter(pub LevelFilter);
+
+impl<S> Filter<S> for CommandLogFilter {
+    fn enabled(&self, metadata: &Metadata<'_>, _: &layer::Context<'_, S>) -> bool {
+        *metadata.level() <= command_log_level().unwrap_or(self.0)
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if COMMAND_LOG_LEVEL_USED.load(Ordering::SeqCst) {
+            Interest::sometimes()
+        } else if *metadata.level() <= self.0 {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+}
+
+// 依次执行命令列表，将出错的命令的序号和错误添加到errors中
+fn execute_command_list(
+    commands: &[CommandItem],
+    options: &ExecuteOptions,
+    errors: &mut Vec<(usize, anyhow::Error)>,
+) {
+    // 第一次遇到设置了log_level的命令时，之前按全局级别缓存的结果不再适用，需要重新判断每处日志
+    if commands.iter().any(|item| item.log_level.is_some())
+        && !COMMAND_LOG_LEVEL_USED.swap(true, Ordering::SeqCst)
+    {
+        tracing::callsite::rebuild_interest_cache();
+    }
+
+    // 在错误信息中标明是第几条、什么类型的命令出错
+    let execute_item = |index: usize| -> Result<()> {
+        options.executed.fetch_add(1, Ordering::SeqCst);
+        let command = &commands[index].command;
+        let log_level = commands[index]
+            .log_level
+            .as_deref()
+            .map(str::parse::<LevelFilter>)
+            .transpose()
+            .map_err(|e| anyhow!("Invalid log_level: {}", e));
+        log_level
+            .and_then(|log_level| {
+                with_log_level(log_level, || execute_with_captured(command, options))
+            })
+            .with_context(|| format!("command #{} ({})", index, command.kind()))
+    };
+    let execute_item = &execute_item;
+
+    let mut index = 0;
+    while index < commands.len() {
+        // 属于同一并行组的连续命令作为一批同时执行，执行完整批之后再继续
+        let group = commands[index].parallel_group.as_deref();
+        let batch_end = parallel_batch_end(commands, index);
+
+        // 从指定的序号继续执行时，跳过之前的命令，假定它们的结果仍然存在
+        let mut batch = Vec::new();
+        for (skipped, item) in commands.iter().enumerate().take(batch_end).skip(index) {
+            if skipped < options.continue_from {
+                info!(
+                    "*** Skipping command #{} ({})",
+                    skipped,
+                    item.command.kind()
+                );
+            } else {
+                batch.push(skipped);
+            }
+        }
+        if batch.is_empty() {
+            index = batch_end;
+            continue;
+        }
+
+        // 收到取消请求时，跳过剩余的命令
+        if options.cancelled.load(Ordering::SeqCst) {
+            error!(
+                "!!! Cancelled, skipping {} remaining command(s)",
+                commands.len() - batch[0]
+            );
+            break;
+        }
+
+        let results: Vec<(usize, Result<()>)> = match (group, batch.as_slice()) {
+            (Some(group), [_, _, ..]) => {
+                info!(
+                    "*** Running {} command(s) of parallel group {} together",
+                    batch.len(),
+                    group
+                );
+                thread::scope(|scope| {
+                    let handles: Vec<_> = batch
+                        .iter()
+                        .map(|&index| (index, scope.spawn(move || execute_item(index))))
+                        .collect();
+                    handles
+                        .into_iter()
+                        .map(|(index, handle)| {
+                            let result = handle
+                                .join()
+                                .unwrap_or_else(|_| Err(anyhow!("command #{} panicked", index)));
+                            (index, result)
+                        })
+                        .collect()
+                })
+            }
+            _ => batch
+                .iter()
+                .map(|&index| (index, execute_item(index)))
+                .collect(),
+        };
+
+        for (index, result) in results {
+            match result {
+                // 可选命令的失败不计入失败次数，因此也不会触发--fail-fast和--max-failures
+                Err(e) if commands[index].optional => {
+                    warn!("!!! Optional command failed: {:#}", e);
+                }
+                Err(e) => {
+                    error!("!!! Error occurred: {:#}", e);
+                    errors.push((index, e));
+                }
+                Ok(()) => {}
+            }
+        }
+
+        // 失败次数达到上限时，跳过剩余的命令
+        if let Some(max_failures) = options.max_failures {
+            if errors.len() as u64 >= max_failures {
+                error!(
+                    "!!! Reached the limit of {} failure(s), skipping {} remaining command(s)",
+                    max_failures,
+                    commands.len() - batch_end
+                );
+                break;
+            }
+        }
+        index = batch_end;
+    }
+}
+
+// 在执行之前检查命令，返回发现的所有问题，不执行任何命令
+// 目前检查run命令的可执行文件能否在PATH中找到，避免执行到一半才发现缺少工具
+pub fn check_commands(commands: &[CommandItem]) -> Vec<anyhow::Error> {
+    let mut errors = Vec::new();
+    for (index, item) in commands.iter().enumerate() {
+        check_command(&item.command, &mut errors, &|e: anyhow::Error| {
+            e.context(format!("command #{} ({})", index, item.command.kind()))
+        });
+    }
+    errors
+}
+
+// 检查所有目标路径都是相对路径，并且不会通过..跳出其所在的目录
+// 需要在拼接输出目录和根目录之前检查，之后的目标路径都已经是绝对路径
+// 设置了cwd的命令检查拼接cwd之前配置中写下的路径
+pub fn check_strict_paths(commands: &[CommandItem]) -> Vec<anyhow::Error> {
+    let mut errors = Vec::new();
+    for (index, item) in commands.iter().enumerate() {
+        let destinations = match &item.written_destinations {
+            Some(written) => written.iter().map(String::as_str).collect(),
+            None => item.command.destinations(),
+        };
+        for destination in destinations {
+            let path = Path::new(destination);
+            let reason = if path.has_root() || path.is_absolute() {
+                "is absolute"
+            } else if path.components().any(|c| c == Component::ParentDir) {
+                "contains .."
+            } else {
+                continue;
+            };
+            errors.push(anyhow!(
+                "command #{} ({}): destination {} {}, which is not allowed with --strict-paths",
+                index,
+                item.command.kind(),
+                destination,
+                reason
+            ));
+        }
+    }
+    errors
+}
+
+fn check_command(
+    command: &Command,
+    errors: &mut Vec<anyhow::Error>,
+    context: &dyn Fn(anyhow::Error) -> anyhow::Error,
+) {
+    match command {
+        Command::Run(run) => {
+            if let Err(e) = check_run_executable(run) {
+                // 允许回退到shell时，找不到的可能是shell的内置命令，只输出警告
+                if run.use_shell_fallback.unwrap_or(true) {
+                    warn!("{:#}, it may still run as a shell builtin", context(e));
+                } else {
+                    errors.push(context(e));
+                }
+            }
+        }
+        Command::Group(group) => {
+            for command in &group.commands {
+                check_command(command, errors, context);
+            }
+        }
+        _ => {}
+    }
+}
+
+// 查找run命令的第一个词对应的可执行文件，含有路径分隔符时相对于当前工作路径
+fn check_run_executable(run: &Run) -> Result<()> {
+    let words = shell_words::split(&run.command)?;
+    let program = words.first().ok_or_else(|| anyhow!("empty command"))?;
+    // 引用了执行时才有值的变量，无法预先确定
+    if program.contains("${") {
+        return Ok(());
+    }
+    match &run.cwd {
+        Some(cwd) => which::which_in(program, env::var_os("PATH"), cwd),
+        None => which::which(program),
+    }
+    .with_context(|| format!("executable {} not found", program))?;
+    Ok(())
+}
+
+// 展开命令中对捕获变量的引用
+// 解析配置时不认识的${NAME}会原样保留，这里只替换已捕获的变量，其余的仍然保留
+// 与解析时相同，通配符字段中的值先经过转义，其余字段再统一替换
+fn substitute_captured(command: &Command, captured: &HashMap<String, String>) -> Result<Command> {
+    let mut command: Command = serde_yaml::from_value(serde_yaml::to_value(command)?)?;
+    for (field, syntax) in glob_fields_mut(&mut command) {
+        *field = substitute_captured_text(field, captured, Some(syntax));
+    }
+    let mut value = serde_yaml::to_value(&command)?;
+    substitute_value_strings(&mut value, &|text: &str| {
+        substitute_captured_text(text, captured, None)
+    });
+    Ok(serde_yaml::from_value(value)?)
+}
+
+// 替换文本中对捕获变量的引用，转义的$${NAME}去掉转义，环境变量和其他变量原样保留
+// syntax不为空时是通配符字段中的第一遍替换：值经过转义，转义的引用和值中的${留给第二遍处理
+fn substitute_captured_text(
+    text: &str,
+    captured: &HashMap<String, String>,
+    syntax: Option<GlobSyntax>,
+) -> String {
+    let re = Regex::new(VARIABLE_PATTERN).unwrap();
+    re.replace_all(text, |caps: &regex::Captures| {
+        let whole = &caps[0];
+        if caps.get(1).is_some() {
+            return match syntax {
+                Some(_) => whole.to_string(),
+                None => whole[1..].to_string(),
+            };
+        }
+        let value = match captured.get(&caps[3]) {
+            Some(value) if caps.get(2).is_none() && caps.get(4).is_none() => value,
+            _ => return whole.to_string(),
+        };
+        match syntax {
+            None => value.clone(),
+            Some(GlobSyntax::Path) => glob::Pattern::escape(value).replace("${", "$${"),
+            Some(GlobSyntax::Set) => globset::escape(value).replace("${", "$${"),
+        }
+    })
+    .into_owned()
+}
+
+// 执行时才有值的变量（matrix中的变量和命令捕获的变量）的转义引用$${NAME}多加一个$，
+// 使它在解析时的变量替换之后仍然保持转义，到执行时替换捕获的变量时才去掉转义，不会被再次展开
+fn protect_captured_escapes(text: &str, captured: &HashSet<String>) -> String {
+    let re = Regex::new(VARIABLE_PATTERN).unwrap();
+    re.replace_all(text, |caps: &regex::Captures| {
+        if caps.get(1).is_some() && caps.get(2).is_none() && captured.contains(&caps[3]) {
+            format!("${}", &caps[0])
+        } else {
+            caps[0].to_string()
+        }
+    })
+    .into_owned()
+}
+
+// 以yaml格式导出变量替换后的配置，标记为secret的变量的值在所有字符串中替换为***
+// 定义项、变量文件和导入的配置都已经展开或合并，导出时一并去掉
+pub fn export_config_yaml(config: &Config, variables: &HashMap<String, String>) -> Result<String> {
+    let mut secrets = Vec::new();
+    for item in active_define_items(&config.define_items) {
+        if !item.secret {
+            continue;
+        }
+        if let Some(value) = variables.get(&item.key) {
+            let value = substitute_variables(value, variables, false)?;
+            if !value.is_empty() {
+                secrets.push(value);
+            }
+        }
+    }
+    // 先替换较长的值，避免其中包含的较短的值被先替换而漏掉
+    secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+
+    let mut value = serde_yaml::to_value(config)?;
+    if let serde_yaml::Value::Mapping(mapping) = &mut value {
+        for key in ["define_items", "vars_file", "import"] {
+            mapping.remove(key);
+        }
+    }
+    substitute_value_strings(&mut value, &|text| {
+        secrets.iter().fold(text.to_string(), |text, secret| {
+            text.replace(secret.as_str(), "***")
+        })
+    });
+    Ok(serde_yaml::to_string(&value)?)
+}
+
+// 对yaml值中的所有字符串应用替换函数
+fn substitute_value_strings(value: &mut serde_yaml::Value, substitute: &dyn Fn(&str) -> String) {
+    match value {
+        serde_yaml::Value::String(text) => *text = substitute(text),
+        serde_yaml::Value::Sequence(sequence) => {
+            for element in sequence {
+                substitute_value_strings(element, substitute);
+            }
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            for (_, element) in mapping.iter_mut() {
+                substitute_value_strings(element, substitute);
+            }
+        }
+        serde_yaml::Value::Tagged(tagged) => {
+            substitute_value_strings(&mut tagged.value, substitute)
+        }
+        _ => {}
+    }
+}
+
+// 将输出目录拼接到所有命令的目标路径之前，已经是绝对路径的目标路径保持不变
+pub fn apply_output_dir(commands: &mut [CommandItem], output_dir: &Path) {
+    for item in commands {
+        for destination in item.command.destinations_mut() {
+            if !Path::new(destination.as_str()).is_absolute() {
+                *destination = output_dir.join(destination.as_str()).display().to_string();
+            }
+        }
+    }
+}
+
+// 将命令中的相对路径转换为相对于base_dir的绝对路径，已经是绝对路径的不受影响
+// 转换后命令的执行不再依赖当前工作路径
+pub fn apply_base_dir(commands: &mut [CommandItem], base_dir: &Path) {
+    for item in commands {
+        for path in item.command.paths_mut() {
+            if !Path::new(path.as_str()).is_absolute() {
+                *path = base_dir.join(path.as_str()).display().to_string();
+            }
+        }
+    }
+}
+
+// 通配符的语法：glob匹配的路径，或globset匹配的模式（额外支持{a,b}）
+#[derive(Clone, Copy)]
+enum GlobSyntax {
+    Path,
+    Set,
+}
+
+// 返回命令中按通配符解析的字段，变量替换时其中的变量值需要转义
+fn glob_fields_mut(command: &mut Command) -> Vec<(&mut String, GlobSyntax)> {
+    match command {
+        Command::Copy(copy) => {
+            let mut fields = vec![(&mut copy.source, GlobSyntax::Path)];
+            fields.extend(
+                copy.modes
+                    .iter_mut()
+                    .flatten()
+                    .map(|rule| (&mut rule.pattern, GlobSyntax::Set)),
+            );
+            fields
+        }
+        Command::Replace(replace) => vec![(&mut replace.source, GlobSyntax::Path)],
+        Command::List(list) => vec![(&mut list.source, GlobSyntax::Path)],
+        Command::Assert(assert) => vec![(&mut assert.path, GlobSyntax::Path)],
+        Command::Run(run) => run
+            .cache_key_files
+            .iter_mut()
+            .flatten()
+            .map(|file| (file, GlobSyntax::Path))
+            .collect(),
+        Command::Group(group) => group
+            .commands
+            .iter_mut()
+            .flat_map(glob_fields_mut)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// 替换通配符字段中的变量，变量的值经过转义，使其中的[、*等字符按字面匹配，例如版本号1.0[beta]
+// 标记为pattern的定义项不转义；找不到定义和转义的变量原样保留，由之后对整个配置的变量替换处理
+// 替换结果中的${再转义为$${，避免被之后的变量替换再次展开
+fn substitute_glob_variables(
+    field: &str,
+    syntax: GlobSyntax,
+    valuemap: &HashMap<String, String>,
+    patterns: &HashSet<&str>,
+    strict_env: bool,
+    unresolved: &mut HashSet<String>,
+) -> Result<String> {
+    let re = Regex::new(VARIABLE_PATTERN).unwrap();
+    let mut result = String::with_capacity(field.len());
+    let mut last_end = 0;
+    for caps in re.captures_iter(field) {
+        let whole = caps.get(0).unwrap().as_str();
+        result.push_str(&field[last_end..caps.get(0).unwrap().start()]);
+        last_end = caps.get(0).unwrap().end();
+        let value = if caps.get(1).is_some() {
+            whole.to_string()
+        } else {
+            substitute_variables_collecting(whole, valuemap, strict_env, unresolved)?
+        };
+        if value == whole {
+            result.push_str(whole);
+            continue;
+        }
+        let is_pattern = caps.get(2).is_none() && patterns.contains(&caps[3]);
+        let value = match syntax {
+            _ if is_pattern => value,
+            GlobSyntax::Path => glob::Pattern::escape(&value),
+            GlobSyntax::Set => globset::escape(&value),
+        };
+        result.push_str(&value.replace("${", "$${"));
+    }
+    result.push_str(&field[last_end..]);
+    Ok(result)
+}
+
+// 将配置中的路径分隔符（/和\）统一转换为当前系统的分隔符，使同一份配置可以在不同系统上使用
+// 含有通配符的路径保持不变，因为在Unix上glob中的\是转义字符
+fn normalize_paths(command: &mut Command) {
+    for path in command.paths_mut() {
+        *path = normalize_separators(path);
+    }
+    match command {
+        Command::Copy(copy) => copy.gitignore_path = normalize_separators(&copy.gitignore_path),
+        Command::List(list) => list.gitignore_path = normalize_separators(&list.gitignore_path),
+        Command::Group(group) => group.commands.iter_mut().for_each(normalize_paths),
+        _ => {}
+    }
+}
+
+// 将命令中的相对路径改写为相对于cwd的路径，没有设置cwd的run命令在cwd下执行
+// 只改写路径而不切换进程的工作路径，因此不影响其他命令，也不妨碍并行执行
+fn apply_cwd(command: &mut Command, cwd: &str) {
+    for path in command.paths_mut() {
+        if !Path::new(path.as_str()).is_absolute() {
+            *path = Path::new(cwd).join(path.as_str()).display().to_string();
+        }
+    }
+    set_run_cwd(command, cwd);
+}
+
+// 应用指定环境的overrides和profile，环境至少要在其中之一中定义
+// 之后移除overrides和profiles，避免再次解析输出的配置时重复应用
+fn apply_environment(config: &mut Config, env: &str) -> Result<()> {
+    let patches = config.overrides.take().unwrap_or_default().remove(env);
+    let profile = config.profiles.take().unwrap_or_default().remove(env);
+    if patches.is_none() && profile.is_none() {
+        return Err(anyhow!("Unknown environment {}", env));
+    }
+    // 先应用overrides，其中的序号对应完整的命令列表
+    if let Some(patches) = patches {
+        apply_overrides(config, &patches)?;
+    }
+    if let Some(profile) = profile {
+        config.define_items.extend(profile.define_items);
+        if let Some(names) = profile.commands {
+            if let Some(name) = names.iter().find(|name| {
+                !config
+                    .command
+                    .iter()
+                    .any(|item| item.name.as_ref() == Some(*name))
+            }) {
+                return Err(anyhow!(
+                    "Profile {} refers to unknown command {}",
+                    env,
+                    name
+                ));
+            }
+            config
+                .command
+                .retain(|item| item.name.as_ref().is_some_and(|name| names.contains(name)));
+        }
+    }
+    Ok(())
+}
+
+// 将overrides中的修改应用到对应的命令上
+// 命令由名称（匹配所有同名命令）或在合并导入之后的命令列表中的序号（从0开始）指定
+fn apply_overrides(config: &mut Config, patches: &BTreeMap<String, String>) -> Result<()> {
+    for (key, value) in patches {
+        let (selector, field) = key
+            .split_once('.')
+            .ok_or_else(|| anyhow!("Invalid override key {}, expected <command>.<field>", key))?;
+        let indices: Vec<usize> = match selector.parse::<usize>() {
+            Ok(index) if index < config.command.len() => vec![index],
+            Ok(index) => {
+                return Err(anyhow!(
+                    "Override {} refers to missing command #{}",
+                    key,
+                    index
+                ))
+            }
+            Err(_) => (0..config.command.len())
+                .filter(|&i| config.command[i].name.as_deref() == Some(selector))
+                .collect(),
+        };
+        if indices.is_empty() {
+            return Err(anyhow!(
+                "Override {} refers to unknown command {}",
+                key,
+                selector
+            ));
+        }
+        for index in indices {
+            let item = &mut config.command[index];
+            *item = override_field(item, field, value)
+                .with_context(|| format!("Failed to apply override {}", key))?;
+        }
+    }
+    Ok(())
+}
+
+// 修改命令中指定路径的字段，路径中的数字用于选择列表中的元素
+// 值按yaml解析，因此可以设置布尔值、数字和列表，不符合字段的类型时作为字符串
+fn override_field(item: &CommandItem, field: &str, value: &str) -> Result<CommandItem> {
+    let mut candidates = vec![serde_yaml::Value::String(value.to_string())];
+    if let Ok(parsed) = serde_yaml::from_str::<serde_yaml::Value>(value) {
+        if !parsed.is_null() && !parsed.is_string() {
+            candidates.insert(0, parsed);
+        }
+    }
+    let mut last_error = None;
+    for candidate in candidates {
+        match set_field(item, field, candidate) {
+            Ok(patched) => return Ok(patched),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow!("Invalid value {}", value)))
+}
+
+// 将命令中指定路径的字段设置为给定的值
+fn set_field(item: &CommandItem, field: &str, value: serde_yaml::Value) -> Result<CommandItem> {
+    let mut root = serde_yaml::to_value(item)?;
+    let mut current = &mut root;
+    for segment in field.split('.') {
+        current = match current {
+            serde_yaml::Value::Sequence(items) => segment
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| items.get_mut(index))
+                .ok_or_else(|| anyhow!("No element {} in {}", segment, field))?,
+            serde_yaml::Value::Mapping(map) => map
+                .entry(serde_yaml::Value::String(segment.to_string()))
+                .or_insert(serde_yaml::Value::Null),
+            _ => return Err(anyhow!("Field {} is not a mapping or list", field)),
+        };
+    }
+    *current = value;
+    let patched: CommandItem = serde_yaml::from_value(root.clone())?;
+    // 未知的字段在反序列化时被忽略，重新序列化后与修改的结果比较，以发现拼错的字段名
+    if serde_yaml::to_value(&patched)? != root {
+        return Err(anyhow!("Unknown field {}", field));
+    }
+    Ok(patched)
+}
+
+// 将默认设置应用到run命令（包括命令组中的）中，命令中已经设置的值优先
+fn apply_run_defaults(command: &mut Command, defaults: &RunDefaults) {
+    match command {
+        Command::Run(run) => {
+            for (name, value) in &defaults.env {
+                run.env.entry(name.clone()).or_insert_with(|| value.clone());
+            }
+            if run.cwd.is_none() {
+                run.cwd = defaults.cwd.as_deref().map(normalize_separators);
+            }
+            if run.use_shell_fallback.is_none() {
+                run.use_shell_fallback = defaults.use_shell_fallback;
+            }
+        }
+        Command::Group(group) => {
+            for command in &mut group.commands {
+                apply_run_defaults(command, defaults);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn set_run_cwd(command: &mut Command, cwd: &str) {
+    match command {
+        Command::Run(run) if run.cwd.is_none() => run.cwd = Some(cwd.to_string()),
+        Command::Group(group) => {
+            for command in &mut group.commands {
+                set_run_cwd(command, cwd);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn normalize_separators(path: &str) -> String {
+    if is_glob_pattern(path) {
+        return path.to_string();
+    }
+    path.replace(['/', '\\'], std::path::MAIN_SEPARATOR_STR)
+}
+
+// 按命令的order字段进行稳定排序，所有命令都没有设置order时保持原有顺序
+// 在按阶段排序之前执行，相当于按order重新排列了配置文件中的命令
+pub fn sort_by_order(commands: Vec<CommandItem>) -> Vec<CommandItem> {
+    if commands.iter().all(|item| item.order.is_none()) {
+        return commands;
+    }
+    commands
+        .into_iter()
+        .sorted_by_key(|item| item.order.unwrap_or(0))
+        .collect()
+}
+
+// 重新执行某条命令的方式
+pub enum Rerun<'a> {
+    // 从该序号的命令继续执行，保留原来的其他参数（包括--stage）
+    ContinueFrom(usize),
+    // 只执行该阶段的命令，去掉原来的--stage、--only-stage和--continue-from
+    Stage(&'a str),
+}
+
+// 根据原来的命令行参数生成重新执行出错命令的命令行，含有空白或引号的参数加上双引号
+pub fn rerun_command_line(args: &[String], rerun: Rerun) -> String {
+    let mut kept = Vec::new();
+    let mut iter = args.iter();
+    let drop_stage = matches!(rerun, Rerun::Stage(_));
+    while let Some(arg) = iter.next() {
+        let (drop, has_value) = match arg.as_str() {
+            "--continue-from" => (true, true),
+            "--stage" | "-s" => (drop_stage, true),
+            "--only-stage" => (drop_stage, false),
+            other => (
+                other.starts_with("--continue-from=")
+                    || (drop_stage && (other.starts_with("--stage=") || other.starts_with("-s"))),
+                false,
+            ),
+        };
+        if !drop {
+            kept.push(arg.clone());
+        } else if has_value {
+            iter.next();
+        }
+    }
+    match rerun {
+        Rerun::ContinueFrom(index) => {
+            kept.extend(["--continue-from".to_string(), index.to_string()])
+        }
+        Rerun::Stage(stage) => kept.extend(["--stage".to_string(), stage.to_string()]),
+    }
+    kept.iter()
+        .map(|arg| {
+            if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+            {
+                format!("\"{}\"", arg.replace('"', "\\\""))
+            } else {
+                arg.clone()
+            }
+        })
+        .join(" ")
+}
+
+// 按阶段对命令进行排序和筛选
+// 各阶段按照其在文件中首次出现的顺序执行，同一阶段内保持原有顺序，未分阶段的命令视为单独的一组。
+// 如果指定了stage，则只保留该阶段的命令；include_unstaged为true时，同时保留未分阶段的命令。
+pub fn select_stage(
+    commands: Vec<CommandItem>,
+    stage: Option<&str>,
+    include_unstaged: bool,
+) -> Vec<CommandItem> {
+    // 记录每个阶段首次出现的位置
+    let mut stage_order: Vec<Option<String>> = Vec::new();
+    for item in &commands {
+        if !stage_order.contains(&item.stage) {
+            stage_order.push(item.stage.clone());
+        }
+    }
+
+    commands
+        .into_iter()
+        .filter(|item| match (stage, &item.stage) {
+            (None, _) => true,
+            (Some(name), Some(item_stage)) => name == item_stage,
+            (Some(_), None) => include_unstaged,
+        })
+        // sorted_by_key是稳定排序，同一阶段内的命令保持原有顺序
+        .sorted_by_key(|item| {
+            stage_order
+                .iter()
+                .position(|s| *s == item.stage)
+                .unwrap_or(usize::MAX)
+        })
+        .collect()
+}
+
+// 两个目录之间的差异，均为相对于目录的路径，按路径排序
+#[derive(Serialize, PartialEq, Debug, Default)]
+pub struct DirDiff {
+    // 只在输出目录中存在的文件
+    pub added: Vec<String>,
+    // 只在参考目录中存在的文件
+    pub removed: Vec<String>,
+    // 两边都存在但大小或内容不同的文件
+    pub modified: Vec<String>,
+}
+
+// 比较输出目录与参考目录中的文件，大小相同时再比较内容的sha256
+pub fn diff_dirs(output_dir: &Path, reference_dir: &Path) -> Result<DirDiff> {
+    let output_files = list_files(output_dir)?;
+    let reference_files = list_files(reference_dir)?;
+
+    let mut diff = DirDiff::default();
+    for (relative_path, output_path) in &output_files {
+        match reference_files.get(relative_path) {
+            None => diff.added.push(relative_path.clone()),
+            Some(reference_path) => {
+                if !same_content(output_path, reference_path)? {
+                    diff.modified.push(relative_path.clone());
+                }
+            }
+        }
+    }
+    diff.removed = reference_files
+        .into_keys()
+        .filter(|relative_path| !output_files.contains_key(relative_path))
+        .collect();
+    Ok(diff)
+}
+
+// 物料清单中的一个文件
+#[derive(Serialize, PartialEq, Debug)]
+pub struct SbomFile {
+    // 以/分隔的相对路径
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+// 生成列出目录下所有文件的路径、大小和sha256的json物料清单，按路径排序，结果与遍历顺序无关
+// 每个目录带有一个名称，有多个目录时路径前加上该名称以区分
+pub fn sbom_json(dirs: &[(String, PathBuf)]) -> Result<String> {
+    let mut files = Vec::new();
+    for (name, dir) in dirs {
+        for (relative_path, path) in list_files(dir)? {
+            files.push(SbomFile {
+                path: if dirs.len() > 1 {
+                    format!(
+                        "{}/{}",
+                        name.replace('\\', "/").trim_end_matches('/'),
+                        relative_path
+                    )
+                } else {
+                    relative_path
+                },
+                size: fs::metadata(&path)?.len(),
+                sha256: to_hex(&file_sha256(&path)?),
+            });
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(serde_json::to_string_pretty(
+        &serde_json::json!({ "files": files }),
+    )?)
+}
+
+// 列出目录下的所有文件，返回以/分隔的相对路径到实际路径的映射，不应用任何忽略规则
+fn list_files(root: &Path) -> Result<BTreeMap<String, PathBuf>> {
+    let mut files = BTreeMap::new();
+    for result in WalkBuilder::new(root).standard_filters(false).build() {
+        let entry = result?;
+        if entry.file_type().is_some_and(|ft| ft.is_file()) {
+            let relative_path = entry.path().strip_prefix(root)?;
+            files.insert(
+                relative_path.to_string_lossy().replace('\\', "/"),
+                entry.path().to_path_buf(),
+            );
+        }
+    }
+    Ok(files)
+}
+
+// 判断两个文件的内容是否相同
+fn same_content(path: &Path, other_path: &Path) -> Result<bool> {
+    if fs::metadata(path)?.len() != fs::metadata(other_path)?.len() {
+        return Ok(false);
+    }
+    Ok(file_sha256(path)? == file_sha256(other_path)?)
+}
+
+// 计算文件内容的sha256
+fn file_sha256(path: &Path) -> Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+// 从yaml文件中反序列化Config
+// 同时返回解析时使用的变量名到值的映射，供执行命令时展开文件内容中的变量
+// 指定base_dir时，配置文件中import的相对路径相对于该目录，否则相对于配置文件所在目录
+pub fn parse_commands_from_yaml(
+    file_path: &str,
+    if_use_define: bool,
+    base_dir: Option<&Path>,
+    env: Option<&str>,
+) -> Result<(Config, HashMap<String, String>)> {
+    // 读取配置文件，并合并其导入的其他配置文件
+    let mut config = load_config_with_imports(Path::new(file_path), base_dir, &mut Vec::new())?;
+    // 在变量替换之前应用，因此修改的值中也可以引用变量
+    let base_defines = config.define_items.len();
+    if let Some(env) = env {
+        apply_environment(&mut config, env)?;
+    }
+    // 将合并后的配置重新序列化为字符串，再统一进行变量替换
+    let mut yaml_content = serde_yaml::to_string(&config)?;
+
+    // 建立变量名到值的映射关系
+    let mut valuemap = HashMap::new();
+    // 找不到定义的变量名
+    let mut unresolved = HashSet::new();
+    if if_use_define {
+        // 内置变量：配置文件所在目录的绝对路径和配置文件名，在解析时确定，不受之后切换工作路径的影响
+        let absolute_path = std::path::absolute(file_path)?;
+        if let Some(config_dir) = absolute_path.parent() {
+            valuemap.insert(
+                "__CONFIG_DIR__".to_string(),
+                config_dir.display().to_string(),
+            );
+        }
+        if let Some(config_file) = absolute_path.file_name() {
+            valuemap.insert(
+                "__CONFIG_FILE__".to_string(),
+                config_file.to_string_lossy().into_owned(),
+            );
+        }
+        // 先加载变量文件中的变量，define_items中的同名变量会覆盖它们
+        if let Some(vars_file) = &config.vars_file {
+            let vars_path = Path::new(file_path)
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(vars_file);
+            valuemap.extend(load_vars_file(&vars_path)?);
+        }
+        // 定义项的值保持原样，引用时由substitute_variables递归展开
+        // 不预先展开，否则值中转义的$${NAME}会在引用时被再次替换
+        // profile中的定义项追加在最后，优先于配置文件中的所有定义（包括限定系统的定义）
+        let (base, profile) = config.define_items.split_at(base_defines);
+        for item in active_define_items(base)
+            .into_iter()
+            .chain(active_define_items(profile))
+        {
+            let value = match &item.from_command {
+                Some(command) => {
+                    let config_dir = absolute_path.parent().unwrap_or(Path::new(""));
+                    define_value_from_command(command, config_dir)
+                        .with_context(|| format!("Failed to evaluate define {}", item.key))?
+                }
+                None => item.value.clone(),
+            };
+            valuemap.insert(item.key.clone(), value);
+        }
+        // matrix中的变量在执行时才确定，解析时保留对它们的引用
+        for key in matrix_keys(&config) {
+            valuemap.remove(key);
+        }
+        // 执行时才有值的变量，对它们的转义引用要保留到执行时
+        let mut late = HashSet::new();
+        for item in &config.command {
+            collect_captured_names(&item.command, &mut late);
+        }
+        late.extend(matrix_keys(&config).cloned());
+
+        // 通配符字段中的变量先替换为转义之后的值，其余的变量再对整个配置统一替换
+        let patterns: HashSet<&str> = config
+            .define_items
+            .iter()
+            .filter(|item| item.pattern)
+            .map(|item| item.key.as_str())
+            .collect();
+        let mut glob_fields: Vec<(&mut String, GlobSyntax)> = config
+            .global_exclude
+            .iter_mut()
+            .flatten()
+            .map(|pattern| (pattern, GlobSyntax::Set))
+            .collect();
+        for item in &mut config.command {
+            glob_fields.extend(glob_fields_mut(&mut item.command));
+        }
+        for (field, syntax) in glob_fields {
+            *field = substitute_glob_variables(
+                field,
+                syntax,
+                &valuemap,
+                &patterns,
+                config.strict_env,
+                &mut unresolved,
+            )?;
+        }
+        yaml_content = serde_yaml::to_string(&config)?;
+
+        // 对指定文本进行变量替换
+        // let mut subst_text = yaml_content.clone();
+        // for item in &config.define_items {
+        //     subst_text = substitute_variables(&subst_text, &valuemap);
+        // }
+
+        let protected: HashMap<String, String> = valuemap
+            .iter()
+            .map(|(key, value)| (key.clone(), protect_captured_escapes(value, &late)))
+            .collect();
+        let subst_text = substitute_variables_collecting(
+            &protect_captured_escapes(&yaml_content, &late),
+            &protected,
+            config.strict_env,
+            &mut unresolved,
+        )?;
+        trace!("substituted config:\n{}", subst_text);
+        yaml_content = subst_text;
+
+        // let mut old_config_str = yaml_content.clone();
+        // let mut new_config_str = old_config_str.clone();
+
+        // 循环替换，直到没有改变为止
+        // loop {
+        //     for item in &config.define_items {
+        //         let key = format!("${{{}}}", item.key);
+        //         let value = &item.value;
+        //         // 使用regex::Regex::new函数创建一个正则表达式对象，用来匹配"{key}"
+        //         let re = Regex::new(&regex::escape(&key)).unwrap();
+        //         new_config_str = re.replace_all(&new_config_str, value).to_string();
+        //     }
+
+        //     if !old_config_str.eq(&new_config_str) {
+        //         old_config_str = new_config_str.clone();
+        //     } else {
+        //         break;
+        //     }
+        // }
+
+        // for item in &config.define_items {
+        //     let key = format!("${{{}}}", item.key);
+        //     let value = &item.value;
+        //     // 使用regex::Regex::new函数创建一个正则表达式对象，用来匹配"{key}"
+        //     let re = Regex::new(&regex::escape(&key)).unwrap();
+        //     new_config_str = re.replace_all(&new_config_str, value).to_string();
+        // }
+
+        // let re = regex::Regex::new(r"$\{(\w+)\}").unwrap();
+        // // 如果匹配到常量还存在，说明常量未完全定义
+        // if re.is_match(&new_config_str) {
+        //     return Err(anyhow!("Invalid configuration"));
+        // }
+        // yaml_content = new_config_str;
+    }
+    let mut config = deserialize_config(&yaml_content)?;
+    for item in &mut config.command {
+        normalize_paths(&mut item.command);
+        // 按cwd改写之后不再需要该字段，避免再次解析输出的配置时重复拼接
+        if let Some(cwd) = item.cwd.take() {
+            item.written_destinations = Some(
+                item.command
+                    .destinations()
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect(),
+            );
+            apply_cwd(&mut item.command, &normalize_separators(&cwd));
+        }
+        // 只填充命令中没有设置的值，因此再次解析输出的配置时结果不变
+        if let Some(defaults) = &config.run_defaults {
+            apply_run_defaults(&mut item.command, defaults);
+        }
+    }
+
+    // 由命令在执行时捕获的变量在解析时还没有值，不视为找不到定义
+    let unresolved = unresolved_variables(&config, unresolved);
+    if !unresolved.is_empty() {
+        warn!("Unresolved variable(s): {}", unresolved.join(", "));
+    }
+    Ok((config, valuemap))
+}
+
+// 对条件表达式求值，表达式中的变量已经替换为实际的值
+// 支持的语法按优先级从低到高为：a || b、a && b、!a、a == b、a != b，以及true/false、yes/no、1/0字面量（不区分大小写，空字符串为false）
+// 比较的两侧去掉首尾空白和成对的引号后按字符串比较，不支持括号
+pub fn evaluate_condition(expression: &str) -> Result<bool> {
+    if expression.contains("||") {
+        return expression.split("||").try_fold(false, |result, part| {
+            Ok(evaluate_condition(part)? || result)
+        });
+    }
+    if expression.contains("&&") {
+        return expression
+            .split("&&")
+            .try_fold(true, |result, part| Ok(evaluate_condition(part)? && result));
+    }
+    let expression = expression.trim();
+    if let Some(negated) = expression
+        .strip_prefix('!')
+        .filter(|rest| !rest.starts_with('='))
+    {
+        return Ok(!evaluate_condition(negated)?);
+    }
+    let unquote = |text: &str| {
+        let text = text.trim();
+        ['"', '\'']
+            .iter()
+            .find_map(|quote| text.strip_prefix(*quote)?.strip_suffix(*quote))
+            .unwrap_or(text)
+            .to_string()
+    };
+    if let Some((left, right)) = expression.split_once("!=") {
+        return Ok(unquote(left) != unquote(right));
+    }
+    if let Some((left, right)) = expression.split_once("==") {
+        return Ok(unquote(left) == unquote(right));
+    }
+    match unquote(expression).to_ascii_lowercase().as_str() {
+        "true" | "yes" | "1" => Ok(true),
+        "false" | "no" | "0" | "" => Ok(false),
+        other => Err(anyhow!("Can not evaluate \"{}\" as a condition", other)),
+    }
+}
+
+// matrix中出现的所有变量名
+fn matrix_keys(config: &Config) -> impl Iterator<Item = &String> {
+    config
+        .matrix
+        .iter()
+        .flatten()
+        .flat_map(|entry| entry.keys())
+}
+
+// 筛选出需要警告的找不到定义的变量，按名称排序
+fn unresolved_variables(config: &Config, unresolved: HashSet<String>) -> Vec<String> {
+    let mut captured = HashSet::new();
+    for item in &config.command {
+        collect_captured_names(&item.command, &mut captured);
+        // replace的替换内容中的${1}、${name}是对正则分组的引用，不是变量
+        collect_replacement_groups(&item.command, &mut captured);
+    }
+    // matrix中的变量同样在执行时才有值
+    captured.extend(matrix_keys(config).cloned());
+    unresolved
+        .into_iter()
+        .filter(|name| !captured.contains(name))
+        .sorted()
+        .collect()
+}
+
+// 收集replace命令的替换内容中引用的正则分组：数字的分组序号，以及正则中定义的分组名
+fn collect_replacement_groups(command: &Command, names: &mut HashSet<String>) {
+    match command {
+        Command::Replace(replace) => {
+            let re = Regex::new(VARIABLE_PATTERN).unwrap();
+            let rules = std::iter::once((&replace.regex, &replace.replacement)).chain(
+                replace
+                    .replacements
+                    .iter()
+                    .flatten()
+                    .map(|rule| (&rule.regex, &rule.replacement)),
+            );
+            for (regex, replacement) in rules {
+                for caps in re.captures_iter(replacement) {
+                    let name = &caps[3];
+                    if name.chars().all(|c| c.is_ascii_digit())
+                        || regex.contains(&format!("(?P<{}>", name))
+                        || regex.contains(&format!("(?<{}>", name))
+                    {
+                        names.insert(name.to_string());
+                    }
+                }
+            }
+        }
+        Command::Group(group) => {
+            for command in &group.commands {
+                collect_replacement_groups(command, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+// 收集命令在执行时会捕获的变量名
+fn collect_captured_names(command: &Command, names: &mut HashSet<String>) {
+    match command {
+        Command::Run(run) => names.extend(run.capture_exit_into.clone()),
+        Command::ManifestVar(manifest) => {
+            names.insert(manifest.into.clone());
+        }
+        Command::Group(group) => {
+            for command in &group.commands {
+                collect_captured_names(command, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+// 在指定目录执行命令，返回去掉首尾空白的stdout，命令失败时返回错误
+fn define_value_from_command(command: &str, current_dir: &Path) -> Result<String> {
+    let words = shell_words::split(command)?;
+    let (program, args) = words
+        .split_first()
+        .ok_or_else(|| anyhow!("Empty command"))?;
+    let output = SysCommand::new(program)
+        .args(args)
+        .current_dir(current_dir)
+        .output()
+        .with_context(|| format!("Failed to execute {}", command))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed with status {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// 读取变量文件，将其中的键值对展开为变量名到值的映射
+// 嵌套的键用.连接，例如 app: { version: 1.0 } 展开为 app.version；列表的元素用下标作为键，例如 targets.0
+fn load_vars_file(path: &Path) -> Result<HashMap<String, String>> {
+    let value: serde_yaml::Value = read_vars_file(path)?;
+    let mut vars = HashMap::new();
+    flatten_vars(&value, "", &mut vars);
+    Ok(vars)
+}
+
+// 读取变量文件中未展开的值
+fn read_vars_file<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read vars file {}", path.display()))?;
+    // json是yaml的子集，两种格式都可以按yaml解析
+    serde_yaml::from_str(&content)
+        .with_context(|| format!("Failed to parse vars file {}", path.display()))
+}
+
+// 递归地展开变量文件中的值
+fn flatten_vars(value: &serde_yaml::Value, prefix: &str, vars: &mut HashMap<String, String>) {
+    let join_key = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", prefix, key)
+        }
+    };
+    match value {
+        serde_yaml::Value::Mapping(mapping) => {
+            for (key, value) in mapping {
+                let key = match key {
+                    serde_yaml::Value::String(key) => key.clone(),
+                    other => serde_yaml::to_string(other)
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                };
+                flatten_vars(value, &join_key(&key), vars);
+            }
+        }
+        serde_yaml::Value::Sequence(sequence) => {
+            for (index, value) in sequence.iter().enumerate() {
+                flatten_vars(value, &join_key(&index.to_string()), vars);
+            }
+        }
+        serde_yaml::Value::String(string) => {
+            vars.insert(prefix.to_string(), string.clone());
+        }
+        serde_yaml::Value::Bool(boolean) => {
+            vars.insert(prefix.to_string(), boolean.to_string());
+        }
+        serde_yaml::Value::Number(number) => {
+            vars.insert(prefix.to_string(), number.to_string());
+        }
+        serde_yaml::Value::Null => {
+            vars.insert(prefix.to_string(), String::new());
+        }
+        serde_yaml::Value::Tagged(tagged) => flatten_vars(&tagged.value, prefix, vars),
+    }
+}
+
+// 读取配置文件，并递归地合并import中列出的配置文件
+// import中的路径相对于导入它的配置文件所在的目录
+// 被导入文件的define_items排在前面，因此同名时以导入方的定义为准
+// import写在command之前时，被导入文件的命令插入到命令列表的开头，否则追加到末尾
+// import_base不为空时代替配置文件所在目录，只对当前文件生效，被导入的文件仍相对于各自所在的目录
+// stack记录当前导入链上的文件，用于检测循环导入
+fn load_config_with_imports(
+    path: &Path,
+    import_base: Option<&Path>,
+    stack: &mut Vec<PathBuf>,
+) -> Result<Config> {
+    let canonical_path = fs::canonicalize(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    if stack.contains(&canonical_path) {
+        return Err(anyhow!("Circular import of {}", path.display()));
+    }
+
+    let content = fs::read_to_string(path)?;
+    let format = ConfigFormat::from_path(path);
+    // 模板配置文件先展开模板，再按展开后的内容解析
+    let content = if is_config_template(path) {
+        render_config_template(path, &content, format)
+            .with_context(|| format!("Failed to render config template {}", path.display()))?
+    } else {
+        content
+    };
+    let mut config = format
+        .deserialize(&content)
+        .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+    let Some(imports) = config.import.take() else {
+        return Ok(config);
+    };
+    let import_first = import_before_command(&format.top_level_keys(&content)?);
+
+    stack.push(canonical_path);
+    let base_dir = import_base.unwrap_or(path.parent().unwrap_or(Path::new("")));
+    let mut define_items = Vec::new();
+    let mut commands = Vec::new();
+    let mut global_exclude = Vec::new();
+    for import in &imports {
+        trace!("Importing {} from {}", import, path.display());
+        let imported = load_config_with_imports(&base_dir.join(import), None, stack)
+            .with_context(|| format!("Failed to import {} from {}", import, path.display()))?;
+        define_items.extend(imported.define_items);
+        commands.extend(imported.command);
+        global_exclude.extend(imported.global_exclude.unwrap_or_default());
+    }
+    stack.pop();
+
+    // 被导入文件的排除规则与当前文件的合并，都对所有copy命令生效
+    global_exclude.extend(config.global_exclude.take().unwrap_or_default());
+    config.global_exclude = (!global_exclude.is_empty()).then_some(global_exclude);
+
+    define_items.append(&mut config.define_items);
+    config.define_items = define_items;
+    if import_first {
+        commands.append(&mut config.command);
+        config.command = commands;
+    } else {
+        config.command.append(&mut commands);
+    }
+    Ok(config)
+}
+
+// 判断配置文件是否是handlebars模板，即扩展名为.hbs，例如 config.yml.hbs
+fn is_config_template(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("hbs"))
+}
+
+// 用handlebars展开配置模板，可以用{{#each}}等语法生成重复的命令
+// 模板中可以引用变量文件中的值（保留列表等嵌套结构）和define_items中的变量
+// 这些变量本身也写在模板中，因此先用空的上下文展开一次，从结果中读取vars_file和define_items，再正式展开
+fn render_config_template(path: &Path, content: &str, format: ConfigFormat) -> Result<String> {
+    let mut handlebars = handlebars::Handlebars::new();
+    // 输出的是配置而不是html，不需要转义
+    handlebars.register_escape_fn(handlebars::no_escape);
+
+    let draft = handlebars.render_template(content, &serde_json::Value::Null)?;
+    let draft_config = format.deserialize(&draft)?;
+
+    let mut context = serde_json::Map::new();
+    let absolute_path = std::path::absolute(path)?;
+    if let Some(config_dir) = absolute_path.parent() {
+        context.insert(
+            "__CONFIG_DIR__".to_string(),
+            config_dir.display().to_string().into(),
+        );
+    }
+    if let Some(vars_file) = &draft_config.vars_file {
+        let vars_path = path.parent().unwrap_or(Path::new("")).join(vars_file);
+        if let serde_json::Value::Object(vars) = read_vars_file(&vars_path)? {
+            context.extend(vars);
+        }
+    }
+    // from_command的定义需要执行命令，只在之后的变量替换中求值，模板中不可用
+    for item in active_define_items(&draft_config.define_items) {
+        if item.from_command.is_none() {
+            context.insert(item.key.clone(), item.value.clone().into());
+        }
+    }
+
+    let rendered = handlebars.render_template(content, &context)?;
+    trace!("rendered config template {}:\n{}", path.display(), rendered);
+    Ok(rendered)
+}
+
+// 判断配置文件中import字段是否写在command字段之前
+fn import_before_command(keys: &[String]) -> bool {
+    let import_index = keys.iter().position(|key| key == "import");
+    let command_index = keys.iter().position(|key| key == "command");
+    match (import_index, command_index) {
+        (Some(import_index), Some(command_index)) => import_index < command_index,
+        _ => true,
+    }
+}
+
+// 配置文件的格式，根据文件扩展名判断
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConfigFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl ConfigFormat {
+    // 根据扩展名判断格式，未知的扩展名按yaml处理
+    // 模板配置文件按去掉.hbs之后的扩展名判断，例如 config.toml.hbs 是toml格式
+    fn from_path(path: &Path) -> ConfigFormat {
+        if is_config_template(path) {
+            if let Some(stem) = path.file_stem() {
+                return ConfigFormat::from_path(Path::new(stem));
+            }
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ConfigFormat::Json,
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+
+    // 将配置文件内容反序列化为Config对象
+    fn deserialize(self, content: &str) -> Result<Config> {
+        Ok(match self {
+            ConfigFormat::Yaml => serde_yaml::from_str(content)?,
+            ConfigFormat::Json => serde_json::from_str(content)?,
+            ConfigFormat::Toml => toml::from_str(content)?,
+        })
+    }
+
+    // 按在文件中出现的顺序返回顶层的字段名
+    fn top_level_keys(self, content: &str) -> Result<Vec<String>> {
+        Ok(match self {
+            ConfigFormat::Yaml => {
+                let value: serde_yaml::Value = serde_yaml::from_str(content)?;
+                value
+                    .as_mapping()
+                    .map(|mapping| {
+                        mapping
+                            .keys()
+                            .filter_map(|key| key.as_str().map(str::to_string))
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            }
+            ConfigFormat::Json => {
+                let value: serde_json::Value = serde_json::from_str(content)?;
+                value
+                    .as_object()
+                    .map(|object| object.keys().cloned().collect())
+                    .unwrap_or_default()
+            }
+            ConfigFormat::Toml => {
+                let table: toml::Table = toml::from_str(content)?;
+                table.keys().cloned().collect()
+            }
+        })
+    }
+}
+
+// 替换字符串中的变量
+// value 是待替换的字符串。
+// valuemap 是一个 HashMap，用于将变量名映射到变量的值。
+// 使用正则来查找 ${} 形式的变量名，并将变量名替换为变量的值。
+// 在替换变量名时，函数会递归地调用自己来解析变量的值。这是因为变量的值可能包含其他变量名，例如 ${VER_MAJOR}.${VER_MINOR}.${VER_PATCH}.${VER_BUILD}。
+// 在这种情况下，函数会首先替换 ${VER_MAJOR}，然后替换 ${VER_MINOR}，以此类推，直到所有变量都被替换为其对应的值。
+// 找不到对应定义的变量名会原样保留，并继续替换后面的变量。
+// ${env:NAME} 形式的变量总是从进程的环境变量中读取，不会查找定义项；而 ${NAME} 只会查找定义项。
+// 环境变量不存在时，strict_env为true则返回错误，否则原样保留。
+// $${NAME} 是转义写法，输出字面量 ${NAME}，不进行替换。
+// 最后，该函数返回替换后的字符串。
+fn substitute_variables(
+    value: &str,
+    valuemap: &HashMap<String, String>,
+    strict_env: bool,
+) -> Result<String> {
+    substitute_variables_collecting(value, valuemap, strict_env, &mut HashSet::new())
+}
+
+// 拆分列表变量时使用的分隔符的变量名，未定义时使用逗号
+const LIST_SEPARATOR_NAME: &str = "__LIST_SEPARATOR__";
+
+// 匹配变量引用：$${NAME}（转义）、${env:NAME}、${NAME}以及${NAME[N]}
+const VARIABLE_PATTERN: &str = r"\$(\$)?\{(?:(env|ENV):)?([\w.]+)(?:\[(\d+)\])?\}";
+
+// 与substitute_variables相同，同时将找不到定义的变量名收集到unresolved中
+fn substitute_variables_collecting(
+    value: &str,
+    valuemap: &HashMap<String, String>,
+    strict_env: bool,
+    unresolved: &mut HashSet<String>,
+) -> Result<String> {
+    let re = Regex::new(VARIABLE_PATTERN).unwrap();
+    let mut result = String::with_capacity(value.len());
+    let mut last_end = 0;
+    for caps in re.captures_iter(value) {
+        let whole = caps.get(0).unwrap();
+        let var_name = caps.get(3).unwrap().as_str();
+        result.push_str(&value[last_end..whole.start()]);
+        last_end = whole.end();
+
+        let var_value = if caps.get(1).is_some() {
+            // 转义的变量，去掉开头多余的$后原样输出
+            result.push_str(&whole.as_str()[1..]);
+            continue;
+        } else if caps.get(2).is_some() {
+            // 从环境变量中读取
+            match env::var(var_name) {
+                Ok(env_value) => env_value,
+                Err(_) if strict_env => {
+                    return Err(anyhow!("Environment variable {} is not set", var_name));
+                }
+                Err(_) => {
+                    warn!("Environment variable {} is not set", var_name);
+                    result.push_str(whole.as_str());
+                    continue;
+                }
+            }
+        } else if let Some(subst_value) = valuemap.get(var_name) {
+            substitute_variables_collecting(subst_value, valuemap, strict_env, unresolved)?
+        } else {
+            unresolved.insert(var_name.to_string());
+            result.push_str(whole.as_str());
+            continue;
+        };
+
+        // ${NAME[N]}按分隔符拆分变量的值，取第N个元素（从0开始），下标越界时原样保留
+        match caps.get(4) {
+            Some(index) => {
+                let separator = valuemap
+                    .get(LIST_SEPARATOR_NAME)
+                    .map(String::as_str)
+                    .unwrap_or(",");
+                let element = index
+                    .as_str()
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| var_value.split(separator).nth(index));
+                match element {
+                    Some(element) => result.push_str(element.trim()),
+                    None => {
+                        unresolved.insert(format!("{}[{}]", var_name, index.as_str()));
+                        result.push_str(whole.as_str());
+                    }
+                }
+            }
+            None => result.push_str(&var_value),
+        }
+    }
+    result.push_str(&value[last_end..]);
+    Ok(result)
+}
+
+// 生成带注释的示例配置文件内容
+// 示例由Config结构体序列化得到，保证与当前的字段定义一致，再在对应位置插入注释
+pub fn example_config_yaml() -> Result<String> {
+    let config = Config {
+        import: None,
+        vars_file: None,
+        define_items: vec![DefineItem {
+            key: "version".to_string(),
+            value: "1.0.0".to_string(),
+            from_command: None,
+            os: None,
+            secret: false,
+            pattern: false,
+        }],
+        command: vec![
+            CommandItem {
+                command: Command::Copy(Copy {
+                    source: "build/".to_string(),
+                    destination: "dist/app-${version}/".to_string(),
+                    gitignore_path: ".gitignore".to_string(),
+                    use_gitignore: true,
+                    ..Default::default()
+                }),
+                stage: None,
+                order: None,
+                optional: false,
+                parallel_group: None,
+                cwd: None,
+                name: None,
+                log_level: None,
+                written_destinations: None,
+            },
+            CommandItem {
+                command: Command::Replace(Replace {
+                    source: "dist/app-${version}/**/*.txt".to_string(),
+                    regex: "VERSION_PLACEHOLDER".to_string(),
+                    replacement: "${version}".to_string(),
+                    ..Default::default()
+                }),
+                stage: None,
+                order: None,
+                optional: false,
+                parallel_group: None,
+                cwd: None,
+                name: None,
+                log_level: None,
+                written_destinations: None,
+            },
+            CommandItem {
+                command: Command::Run(Run {
+                    command: "echo 'Build complete!'".to_string(),
+                    ..Default::default()
+                }),
+                stage: None,
+                order: None,
+                optional: false,
+                parallel_group: None,
+                cwd: None,
+                name: None,
+                log_level: None,
+                written_destinations: None,
+            },
+        ],
+        strict_env: false,
+        global_exclude: None,
+        matrix: None,
+        run_if: None,
+        run_defaults: None,
+        overrides: None,
+        profiles: None,
+    };
+    let yaml = serde_yaml::to_string(&config)?;
+
+    let mut result = String::from("# autopackager配置文件示例\n");
+    for line in yaml.lines() {
+        let comment = match line {
+            "define_items:" => Some("# 定义变量，可以在配置的其他位置通过 ${key} 引用"),
+            "command:" => Some("# 按顺序执行的命令列表"),
+            "- type: Copy" => {
+                Some("# 复制source下的文件到destination，可选择是否使用gitignore规则")
+            }
+            "- type: Replace" => Some("# 替换source匹配到的文件中正则匹配到的内容"),
+            "- type: Run" => Some("# 运行指定命令"),
+            "strict_env: false" => Some("# ${env:NAME} 引用的环境变量不存在时是否报错"),
+            _ => None,
+        };
+        if let Some(comment) = comment {
+            result.push_str(comment);
+            result.push('\n');
+        }
+        result.push_str(line);
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+// 生成描述配置文件格式的JSON Schema，直接由Config结构体推导，因此与字段定义保持一致
+pub fn config_schema_json() -> Result<String> {
+    let schema = schemars::schema_for!(Config);
+    Ok(serde_json::to_string_pretty(&schema)?)
+}
+
+// 根据JSON Schema列出所有命令类型及其字段、类型和是否可选，直接由结构体推导，不会与实现不一致
+pub fn list_commands_text() -> Result<String> {
+    let schema = serde_json::to_value(schemars::schema_for!(Command))?;
+    let defs = &schema["$defs"];
+    let variants = schema["oneOf"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Command schema has no variants"))?;
+    let mut text = String::new();
+    for variant in variants {
+        let name = variant["properties"]["type"]["const"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Command variant without a type name"))?;
+        text.push_str(name);
+        text.push('\n');
+        let def = match variant["$ref"].as_str() {
+            Some(reference) => &defs[reference.trim_start_matches("#/$defs/")],
+            None => variant,
+        };
+        let required: Vec<&str> = def["required"]
+            .as_array()
+            .map(|fields| fields.iter().filter_map(|f| f.as_str()).collect())
+            .unwrap_or_default();
+        if let Some(properties) = def["properties"].as_object() {
+            for (field, field_schema) in properties {
+                if field == "type" {
+                    continue;
+                }
+                let optional = if required.contains(&field.as_str()) {
+                    "required".to_string()
+                } else {
+                    match field_schema.get("default") {
+                        Some(default) if !default.is_null() => {
+                            format!("optional, default {}", default)
+                        }
+                        _ => "optional".to_string(),
+                    }
+                };
+                text.push_str(&format!(
+                    "  {}: {} ({})\n",
+                    field,
+                    schema_type_name(field_schema, defs),
+                    optional
+                ));
+            }
+        }
+    }
+    Ok(text)
+}
+
+// 把schema中的类型描述转换为便于阅读的名称，忽略表示Option的null
+// 引用的枚举直接展开为可选值，引用的结构体在名称后附上其字段
+fn schema_type_name(schema: &serde_json::Value, defs: &serde_json::Value) -> String {
+    if let Some(reference) = schema["$ref"].as_str() {
+        if reference == "#" {
+            return "Command".to_string();
+        }
+        let name = reference.trim_start_matches("#/$defs/");
+        let def = &defs[name];
+        if def["enum"].is_array() {
+            return schema_type_name(def, defs);
+        }
+        return match def["properties"].as_object() {
+            Some(properties) => format!(
+                "{} {{ {} }}",
+                name,
+                properties
+                    .iter()
+                    .map(|(field, field_schema)| format!(
+                        "{}: {}",
+                        field,
+                        schema_type_name(field_schema, defs)
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            None => name.to_string(),
+        };
+    }
+    if let Some(values) = schema["enum"].as_array() {
+        return values
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(" | ");
+    }
+    for key in ["anyOf", "oneOf"] {
+        if let Some(options) = schema[key].as_array() {
+            return options
+                .iter()
+                .filter(|option| option["type"] != "null")
+                .map(|option| schema_type_name(option, defs))
+                .collect::<Vec<_>>()
+                .join(" | ");
+        }
+    }
+    let types: Vec<&str> = match &schema["type"] {
+        serde_json::Value::String(t) => vec![t.as_str()],
+        serde_json::Value::Array(ts) => ts
+            .iter()
+            .filter_map(|t| t.as_str())
+            .filter(|t| *t != "null")
+            .collect(),
+        _ => Vec::new(),
+    };
+    let name = types
+        .iter()
+        .map(|t| match *t {
+            "array" => format!("list of {}", schema_type_name(&schema["items"], defs)),
+            "object" if schema["additionalProperties"].is_object() => format!(
+                "map of {}",
+                schema_type_name(&schema["additionalProperties"], defs)
+            ),
+            other => other.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" | ");
+    if name.is_empty() {
+        "any".to_string()
+    } else {
+        name
+    }
+}
+
+// 定义一个函数，用于从yaml字符串反序列化为Config对象
+pub fn deserialize_config(yaml: &str) -> Result<Config> {
+    Ok(serde_yaml::from_str(yaml)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    // 测试yaml文件解析
+    fn parse_correct_commands_test() -> Result<()> {
+        // 从tests/config.yml文件中解析出Config对象
+        let config = parse_commands_from_yaml("tests/ori_data/config.yml", true, None, None)?.0;
+        let expected_config =
+            parse_commands_from_yaml("tests/data/config.yml", false, None, None)?.0;
+        // 使用assert_eq!宏来断言两个Config对象是否相等
+        assert_eq!(config, expected_config);
+        // 如果没有错误，就返回Ok(())
+        Ok(())
+    }
+
+    #[test]
+    // 测试命令执行函数
+    fn test_execute_run() {
+        // 创建一个Run结构体实例
+        let run = Run {
+            command: "echo hello".to_string(),
+            ..Default::default()
+        };
+
+        // 调用execute_run函数，并断言它返回Ok(())
+        assert_eq!((), execute_run(&run, &ExecuteOptions::default()).unwrap());
+    }
+
+    #[test]
+    // 测试阶段的排序和筛选
+    fn test_select_stage() {
+        let item = |command: &str, stage: Option<&str>| CommandItem {
+            command: Command::Run(Run {
+                command: command.to_string(),
+                ..Default::default()
+            }),
+            stage: stage.map(str::to_string),
+            order: None,
+            optional: false,
+            parallel_group: None,
+            cwd: None,
+            name: None,
+            log_level: None,
+            written_destinations: None,
+        };
+        let commands = vec![
+            item("a1", Some("a")),
+            item("n1", None),
+            item("b1", Some("b")),
+            item("a2", Some("a")),
+        ];
+        let names = |commands: Vec<CommandItem>| -> Vec<String> {
+            commands
+                .into_iter()
+                .map(|item| match item.command {
+                    Command::Run(run) => run.command,
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+
+        let all = select_stage(commands, None, false);
+        assert_eq!(names(all), ["a1", "a2", "n1", "b1"]);
+
+        // 按order排序后，阶段的先后也按排序后的位置决定
+        let commands = vec![
+            CommandItem {
+                order: Some(2),
+                ..item("a1", Some("a"))
+            },
+            item("n1", None),
+            CommandItem {
+                order: Some(1),
+                ..item("b1", Some("b"))
+            },
+            item("n2", None),
+        ];
+        let all = select_stage(sort_by_order(commands), None, false);
+        assert_eq!(names(all), ["n1", "n2", "b1", "a1"]);
+
+        let commands = vec![
+            item("a1", Some("a")),
+            item("n1", None),
+            item("b1", Some("b")),
+        ];
+        let only_b = select_stage(commands, Some("b"), false);
+        assert_eq!(names(only_b), ["b1"]);
+
+        let commands = vec![
+            item("a1", Some("a")),
+            item("n1", None),
+            item("b1", Some("b")),
+        ];
+        let b_with_unstaged = select_stage(commands, Some("b"), true);
+        assert_eq!(names(b_with_unstaged), ["n1", "b1"]);
+    }
+
+    #[test]
+    // 测试glob复制时保留基准路径以下的目录结构
+    fn test_copy_glob_preserves_structure() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("build/a/b"))?;
+        fs::write(root.join("build/top.wasm"), "top")?;
+        fs::write(root.join("build/a/x.wasm"), "x")?;
+        fs::write(root.join("build/a/b/y.wasm"), "y")?;
+        fs::write(root.join("build/a/readme.txt"), "readme")?;
+
+        let copy = Copy {
+            source: format!("{}/build/**/*.wasm", root.display()),
+            destination: format!("{}/dist", root.display()),
+            gitignore_path: ".gitignore".to_string(),
+            use_gitignore: false,
+            ..Default::default()
+        };
+        execute_copy(&copy, &ExecuteOptions::default())?;
+
+        let dist = root.join("dist");
+        assert_eq!(fs::read_to_string(dist.join("top.wasm"))?, "top");
+        assert_eq!(fs::read_to_string(dist.join("a/x.wasm"))?, "x");
+        assert_eq!(fs::read_to_string(dist.join("a/b/y.wasm"))?, "y");
+        // 不应被拍平到目标根目录，也不应复制未匹配的文件
+        assert!(!dist.join("x.wasm").exists());
+        assert!(!dist.join("y.wasm").exists());
+        assert!(!dist.join("a/readme.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    // 测试glob基准路径的计算
+    fn test_glob_base() {
+        assert_eq!(glob_base("build/**/*.wasm"), PathBuf::from("build"));
+        assert_eq!(glob_base("./build/out/*.txt"), PathBuf::from("build/out"));
+        assert_eq!(glob_base("*.txt"), PathBuf::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    // 测试向命令的标准输入写入内容
+    fn test_execute_run_with_stdin() {
+        let run = Run {
+            command: "grep -q hello".to_string(),
+            stdin: Some("hello world".to_string()),
+            ..Default::default()
+        };
+        assert!(execute_run(&run, &ExecuteOptions::default()).is_ok());
+
+        let run = Run {
+            command: "grep -q hello".to_string(),
+            stdin: Some("goodbye".to_string()),
+            ..Default::default()
+        };
+        assert!(execute_run(&run, &ExecuteOptions::default()).is_err());
+    }
+
+    #[test]
+    // 测试定义项变量和环境变量的替换
+    fn test_substitute_variables() -> Result<()> {
+        env::set_var("AUTOPACKAGER_TEST_SUBST", "from-env");
+        let valuemap = HashMap::from([
+            (
+                "AUTOPACKAGER_TEST_SUBST".to_string(),
+                "from-define".to_string(),
+            ),
+            ("VER".to_string(), "${MAJOR}.${MINOR}".to_string()),
+            ("MAJOR".to_string(), "1".to_string()),
+            ("MINOR".to_string(), "2".to_string()),
+        ]);
+
+        assert_eq!(
+            substitute_variables("${AUTOPACKAGER_TEST_SUBST}", &valuemap, false)?,
+            "from-define"
+        );
+        assert_eq!(
+            substitute_variables("${env:AUTOPACKAGER_TEST_SUBST}", &valuemap, false)?,
+            "from-env"
+        );
+        // 未定义的变量原样保留，且不影响后面变量的替换
+        assert_eq!(
+            substitute_variables("${UNKNOWN}-${VER}", &valuemap, false)?,
+            "${UNKNOWN}-1.2"
+        );
+        assert_eq!(
+            substitute_variables("${env:AUTOPACKAGER_TEST_UNSET}", &valuemap, false)?,
+            "${env:AUTOPACKAGER_TEST_UNSET}"
+        );
+        assert!(substitute_variables("${env:AUTOPACKAGER_TEST_UNSET}", &valuemap, true).is_err());
+
+        // 收集找不到定义的变量名，包括定义项的值中引用的变量
+        let valuemap = HashMap::from([(
+            "PATH_VAR".to_string(),
+            "${MISSING_IN_DEFINE}/bin".to_string(),
+        )]);
+        let mut unresolved = HashSet::new();
+        substitute_variables_collecting(
+            "${PATH_VAR} ${TYPO} $${ESCAPED}",
+            &valuemap,
+            false,
+            &mut unresolved,
+        )?;
+        assert_eq!(
+            unresolved,
+            HashSet::from(["MISSING_IN_DEFINE".to_string(), "TYPO".to_string()])
+        );
+        Ok(())
+    }
+
+    #[test]
+    // 测试$${NAME}转义为字面量${NAME}
+    fn test_substitute_variables_escape() -> Result<()> {
+        let valuemap = HashMap::from([
+            ("FOO".to_string(), "foo".to_string()),
+            ("TEMPLATE".to_string(), "$${FOO}-${FOO}".to_string()),
+        ]);
+
+        assert_eq!(substitute_variables("$${FOO}", &valuemap, false)?, "${FOO}");
+        assert_eq!(substitute_variables("${FOO}", &valuemap, false)?, "foo");
+        assert_eq!(
+            substitute_variables(
+                "${FOO} $${FOO} $${env:AUTOPACKAGER_TEST_UNSET}",
+                &valuemap,
+                true
+            )?,
+            "foo ${FOO} ${env:AUTOPACKAGER_TEST_UNSET}"
+        );
+        // 定义项的值中的转义在引用时同样只展开一次
+        assert_eq!(
+            substitute_variables("${TEMPLATE}", &valuemap, false)?,
+            "${FOO}-foo"
+        );
+        Ok(())
+    }
+
+    #[test]
+    // 测试按下标引用列表变量中的元素
+    fn test_substitute_variables_index() -> Result<()> {
+        let mut valuemap = HashMap::from([
+            ("TARGETS".to_string(), "linux, windows,macos".to_string()),
+            ("FIRST".to_string(), "${TARGETS[0]}".to_string()),
+        ]);
+        assert_eq!(
+            substitute_variables("${TARGETS[1]}/${TARGETS[2]}", &valuemap, false)?,
+            "windows/macos"
+        );
+        assert_eq!(substitute_variables("${FIRST}", &valuemap, false)?, "linux");
+        assert_eq!(
+            substitute_variables("$${TARGETS[0]}", &valuemap, false)?,
+            "${TARGETS[0]}"
+        );
+
+        // 下标越界时原样保留，并视为找不到定义
+        let mut unresolved = HashSet::new();
+        assert_eq!(
+            substitute_variables_collecting("${TARGETS[3]}", &valuemap, false, &mut unresolved)?,
+            "${TARGETS[3]}"
+        );
+        assert!(unresolved.contains("TARGETS[3]"));
+
+        valuemap.insert(LIST_SEPARATOR_NAME.to_string(), ";".to_string());
+        valuemap.insert("PATHS".to_string(), "a,b;c".to_string());
+        assert_eq!(substitute_variables("${PATHS[1]}", &valuemap, false)?, "c");
+        Ok(())
+    }
+
+    #[test]
+    // 测试二进制模式下的等长替换
+    fn test_replace_bytes_fixed_length() -> Result<()> {
+        let regex = BytesRegex::new(r"/opt/app\x00")?;
+        let content = b"\x7fELF/opt/app\x00\xff";
+        let replaced = replace_bytes_fixed_length(&regex, content, "/srv")?;
+        assert_eq!(replaced, b"\x7fELF/srv\x00\x00\x00\x00\x00\xff");
+        assert_eq!(replaced.len(), content.len());
+        // 替换内容比匹配内容长时报错
+        assert!(replace_bytes_fixed_length(&regex, content, "/a/much/longer/path").is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    // 测试只在stderr匹配retry_on时重试
+    fn test_execute_run_retry_on() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let counter = dir.path().join("attempts");
+        // 每次执行都追加一行，用行数来统计执行次数
+        let command = format!(
+            "sh -c 'echo x >> {}; echo connection reset >&2; exit 1'",
+            counter.display()
+        );
+
+        let run = Run {
+            command: command.clone(),
+            retries: 2,
+            retry_on: Some("connection reset".to_string()),
+            ..Default::default()
+        };
+        assert!(execute_run(&run, &ExecuteOptions::default()).is_err());
+        assert_eq!(fs::read_to_string(&counter)?.lines().count(), 3);
+
+        fs::remove_file(&counter)?;
+        let run = Run {
+            command,
+            retries: 2,
+            retry_on: Some("syntax error".to_string()),
+            ..Default::default()
+        };
+        assert!(execute_run(&run, &ExecuteOptions::default()).is_err());
+        assert_eq!(fs::read_to_string(&counter)?.lines().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    // 测试生成的示例配置可以被正确解析
+    fn test_example_config_yaml() -> Result<()> {
+        let yaml = example_config_yaml()?;
+        let config = deserialize_config(&yaml)?;
+        assert_eq!(config.command.len(), 3);
+        assert_eq!(config.define_items[0].key, "version");
+        Ok(())
+    }
+
+    #[test]
+    // 测试导入其他配置文件
+    fn test_import_config() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("shared"))?;
+        fs::write(
+            root.join("shared/common.yml"),
+            "define_items:\n  - key: name\n    value: shared\n  - key: greeting\n    value: hello\ncommand:\n  - type: Run\n    command: \"echo ${greeting}\"\n",
+        )?;
+        fs::write(
+            root.join("before.yml"),
+            "import:\n  - shared/common.yml\ndefine_items:\n  - key: name\n    value: main\ncommand:\n  - type: Run\n    command: \"echo ${name}\"\n",
+        )?;
+        fs::write(
+            root.join("after.yml"),
+            "command:\n  - type: Run\n    command: \"echo ${name}\"\nimport:\n  - shared/common.yml\n",
+        )?;
+
+        let commands = |config: Config| -> Vec<String> {
+            config
+                .command
+                .into_iter()
+                .map(|item| match item.command {
+                    Command::Run(run) => run.command,
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+
+        // 导入方的定义覆盖被导入文件中的同名定义，import在command之前时被导入的命令在前
+        let config = parse_commands_from_yaml(
+            &root.join("before.yml").display().to_string(),
+            true,
+            None,
+            None,
+        )?
+        .0;
+        assert_eq!(commands(config), ["echo hello", "echo main"]);
+
+        let config = parse_commands_from_yaml(
+            &root.join("after.yml").display().to_string(),
+            true,
+            None,
+            None,
+        )?
+        .0;
+        assert_eq!(commands(config), ["echo shared", "echo hello"]);
+
+        // 循环导入时报错
+        fs::write(root.join("a.yml"), "import:\n  - b.yml\n")?;
+        fs::write(root.join("b.yml"), "import:\n  - a.yml\n")?;
+        assert!(parse_commands_from_yaml(
+            &root.join("a.yml").display().to_string(),
+            true,
+            None,
+            None
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试增量复制跳过未改变的文件
+    fn test_copy_incremental() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("src/a.txt"), "aaa")?;
+        fs::write(root.join("src/b.txt"), "bbb")?;
+
+        let copy = Copy {
+            source: format!("{}/src", root.display()),
+            destination: format!("{}/dist", root.display()),
+            gitignore_path: ".gitignore".to_string(),
+            incremental: true,
+            ..Default::default()
+        };
+        execute_copy(&copy, &ExecuteOptions::default())?;
+
+        // 目标文件大小相同且更新时，不会被源文件覆盖；大小不同时重新复制
+        fs::write(root.join("dist/a.txt"), "xxx")?;
+        fs::write(root.join("src/b.txt"), "bbbb")?;
+        execute_copy(&copy, &ExecuteOptions::default())?;
+        assert_eq!(fs::read_to_string(root.join("dist/a.txt"))?, "xxx");
+        assert_eq!(fs::read_to_string(root.join("dist/b.txt"))?, "bbbb");
+        Ok(())
+    }
+
+    #[test]
+    // 测试检查命令输出是否符合预期
+    fn test_execute_run_expect_stdout() {
+        let run = Run {
+            command: "echo version 1.2.3".to_string(),
+            expect_stdout: Some(r"version 1\.2\.\d+".to_string()),
+            ..Default::default()
+        };
+        assert!(execute_run(&run, &ExecuteOptions::default()).is_ok());
+
+        let run = Run {
+            command: "echo version 2.0.0".to_string(),
+            expect_stdout: Some(r"version 1\.2\.\d+".to_string()),
+            ..Default::default()
+        };
+        assert!(execute_run(&run, &ExecuteOptions::default()).is_err());
+    }
+
+    #[test]
+    // 测试命令成功但输出到stderr时视为失败，匹配忽略正则的行除外
+    fn test_execute_run_fail_on_stderr() {
+        let run = Run {
+            command: "sh -c \"echo progress 50% >&2\"".to_string(),
+            fail_on_stderr: true,
+            ignore_stderr_pattern: Some(r"^progress \d+%$".to_string()),
+            ..Default::default()
+        };
+        assert!(execute_run(&run, &ExecuteOptions::default()).is_ok());
+
+        let run = Run {
+            command: "sh -c \"echo progress 50% >&2; echo warning: deprecated >&2\"".to_string(),
+            fail_on_stderr: true,
+            ignore_stderr_pattern: Some(r"^progress \d+%$".to_string()),
+            ..Default::default()
+        };
+        assert!(execute_run(&run, &ExecuteOptions::default()).is_err());
+    }
+
+    #[test]
+    // 测试自定义命令通过标准输入和环境变量接收参数
+    fn test_execute_custom() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output = dir.path().join("output.txt");
+        let handler = dir.path().join("handler.sh");
+        fs::write(
+            &handler,
+            "#!/bin/sh\ncat > \"$AUTOPACKAGER_ARG_OUTPUT_FILE\"\n",
+        )?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&handler, fs::Permissions::from_mode(0o755))?;
+        }
+
+        let custom = Custom {
+            handler: handler.display().to_string(),
+            args: HashMap::from([("output-file".to_string(), output.display().to_string())]),
+        };
+        execute_custom(&custom, &ExecuteOptions::default())?;
+        let args: HashMap<String, String> = serde_json::from_str(&fs::read_to_string(&output)?)?;
+        assert_eq!(args, custom.args);
+
+        let custom = Custom {
+            handler: "false".to_string(),
+            ..Default::default()
+        };
+        assert!(execute_custom(&custom, &ExecuteOptions::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试捕获退出码后，退出码非0不视为失败，且之后的命令可以引用该变量
+    fn test_execute_run_capture_exit_into() -> Result<()> {
+        let commands = vec![
+            CommandItem {
+                command: Command::Run(Run {
+                    command: "sh -c \"exit 3\"".to_string(),
+                    capture_exit_into: Some("probe".to_string()),
+                    ..Default::default()
+                }),
+                stage: None,
+                order: None,
+                optional: false,
+                parallel_group: None,
+                cwd: None,
+                name: None,
+                log_level: None,
+                written_destinations: None,
+            },
+            CommandItem {
+                command: Command::Run(Run {
+                    command: "echo ${probe}".to_string(),
+                    expect_stdout: Some(r"^3\s*$".to_string()),
+                    ..Default::default()
+                }),
+                stage: None,
+                order: None,
+                optional: false,
+                parallel_group: None,
+                cwd: None,
+                name: None,
+                log_level: None,
+                written_destinations: None,
+            },
+        ];
+        let options = ExecuteOptions::default();
+        assert!(execute_commands(&commands, &options).is_ok());
+        assert_eq!(options.captured_variables()["probe"], "3");
+        Ok(())
+    }
+
+    #[test]
+    // 测试清空环境变量后只保留keep_env中列出的变量
+    fn test_execute_run_clear_env() {
+        env::set_var("AUTOPACKAGER_TEST_CLEAR_ENV", "leaked");
+        let run = Run {
+            command: "sh -c \"echo [$AUTOPACKAGER_TEST_CLEAR_ENV]\"".to_string(),
+            clear_env: true,
+            keep_env: vec!["PATH".to_string()],
+            expect_stdout: Some(r"^\[\]".to_string()),
+            ..Default::default()
+        };
+        assert!(execute_run(&run, &ExecuteOptions::default()).is_ok());
+
+        let run = Run {
+            keep_env: vec![
+                "PATH".to_string(),
+                "AUTOPACKAGER_TEST_CLEAR_ENV".to_string(),
+            ],
+            expect_stdout: Some(r"^\[leaked\]".to_string()),
+            ..run
+        };
+        assert!(execute_run(&run, &ExecuteOptions::default()).is_ok());
+    }
+
+    #[test]
+    // 测试命令组中任意命令失败时从头重试整个组
+    fn test_execute_group_retry() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let marker = dir.path().join("marker");
+        // 第一次执行时第二条命令失败，重试时整个组都成功
+        let group = Group {
+            commands: vec![
+                Command::Run(Run {
+                    command: format!("sh -c \"echo x >> {}\"", marker.display()),
+                    ..Default::default()
+                }),
+                Command::Run(Run {
+                    command: format!("sh -c \"test $(wc -l < {}) -ge 2\"", marker.display()),
+                    ..Default::default()
+                }),
+            ],
+            retries: 1,
+            delay_secs: 0,
+        };
+        execute_group(&group, &ExecuteOptions::default())?;
+        assert_eq!(fs::read_to_string(&marker)?.lines().count(), 2);
+
+        let group = Group {
+            retries: 0,
+            ..group
+        };
+        fs::remove_file(&marker)?;
+        assert!(execute_group(&group, &ExecuteOptions::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试关闭shell回退后，找不到可执行文件时直接返回错误
+    fn test_execute_run_without_shell_fallback() {
+        let run = Run {
+            command: "autopackager-no-such-tool --version".to_string(),
+            use_shell_fallback: Some(false),
+            ..Default::default()
+        };
+        let error = execute_run(&run, &ExecuteOptions::default()).unwrap_err();
+        assert!(format!("{:#}", error).contains("autopackager-no-such-tool"));
+    }
+
+    #[test]
+    // 测试toml格式的配置文件与等价的yaml配置文件解析结果相同
+    fn parse_toml_commands_test() -> Result<()> {
+        let config = parse_commands_from_yaml("tests/ori_data/config.toml", true, None, None)?.0;
+        let expected_config =
+            parse_commands_from_yaml("tests/data/config.yml", false, None, None)?.0;
+        assert_eq!(config, expected_config);
+        Ok(())
+    }
+
+    #[test]
+    // 测试变量文件中嵌套的键展开为用.连接的变量名
+    fn test_vars_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::write(
+            root.join("vars.yml"),
+            "app:\n  version: 1.2.0\n  name: demo\ntargets: [x86, arm]\n",
+        )?;
+        fs::write(
+            root.join("config.yml"),
+            "vars_file: vars.yml\ndefine_items:\n  - key: app.name\n    value: override\ncommand:\n  - type: Run\n    command: \"echo ${app.name}-${app.version}-${targets.1}\"\n",
+        )?;
+
+        let config = parse_commands_from_yaml(
+            &root.join("config.yml").display().to_string(),
+            true,
+            None,
+            None,
+        )?
+        .0;
+        match &config.command[0].command {
+            Command::Run(run) => assert_eq!(run.command, "echo override-1.2.0-arm"),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    // 测试复制单个文件时，目标路径作为文件路径或目录路径
+    fn test_copy_single_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::write(root.join("foo.txt"), "foo")?;
+        fs::create_dir_all(root.join("existing"))?;
+
+        let copy_to = |source: &str, destination: &str| {
+            let copy = Copy {
+                source: format!("{}/{}", root.display(), source),
+                destination: format!("{}/{}", root.display(), destination),
+                gitignore_path: ".gitignore".to_string(),
+                ..Default::default()
+            };
+            execute_copy(&copy, &ExecuteOptions::default())
+        };
+
+        // 目标路径不是目录时，复制并重命名
+        copy_to("foo.txt", "out/renamed.txt")?;
+        assert_eq!(fs::read_to_string(root.join("out/renamed.txt"))?, "foo");
+        // 目标路径以分隔符结尾时，复制到该目录下
+        copy_to("foo.txt", "dir/")?;
+        assert_eq!(fs::read_to_string(root.join("dir/foo.txt"))?, "foo");
+        // 目标路径是已存在的目录时，复制到该目录下
+        copy_to("foo.txt", "existing")?;
+        assert_eq!(fs::read_to_string(root.join("existing/foo.txt"))?, "foo");
+        // glob只匹配到一个文件时同样适用
+        copy_to("f*.txt", "glob/renamed.txt")?;
+        assert_eq!(fs::read_to_string(root.join("glob/renamed.txt"))?, "foo");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    // 测试取消时结束正在运行的子进程
+    fn test_execute_run_cancelled() {
+        let options = ExecuteOptions::default();
+        let cancelled = options.cancelled.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            cancelled.store(true, Ordering::SeqCst);
+        });
+
+        let run = Run {
+            command: "sleep 10".to_string(),
+            ..Default::default()
+        };
+        let start = std::time::Instant::now();
+        assert!(execute_run(&run, &options).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    // 测试按目标路径模板复制文件
+    fn test_copy_destination_template() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/sub"))?;
+        fs::write(root.join("src/libfoo.so"), "so")?;
+        fs::write(root.join("src/sub/foo.h"), "h")?;
+
+        let copy = Copy {
+            source: format!("{}/src", root.display()),
+            destination: format!("{}/dist", root.display()),
+            gitignore_path: ".gitignore".to_string(),
+            destination_template: Some("{ext}/{stem}-copy.{ext}".to_string()),
+            ..Default::default()
+        };
+        execute_copy(&copy, &ExecuteOptions::default())?;
+        assert_eq!(
+            fs::read_to_string(root.join("dist/so/libfoo-copy.so"))?,
+            "so"
+        );
+        assert_eq!(fs::read_to_string(root.join("dist/h/foo-copy.h"))?, "h");
+        Ok(())
+    }
+
+    #[test]
+    // 测试复制时展开文本文件中的变量，二进制文件原样复制
+    fn test_copy_substitute() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("src/run.sh"), "echo ${version} $${HOME}")?;
+        fs::write(root.join("src/data.bin"), b"${version}\0")?;
+
+        let copy = Copy {
+            source: format!("{}/src", root.display()),
+            destination: format!("{}/dist", root.display()),
+            gitignore_path: ".gitignore".to_string(),
+            substitute: true,
+            ..Default::default()
+        };
+        let options = ExecuteOptions {
+            variables: HashMap::from([("version".to_string(), "1.0.0".to_string())]),
+            ..Default::default()
+        };
+        execute_copy(&copy, &options)?;
+        assert_eq!(
+            fs::read_to_string(root.join("dist/run.sh"))?,
+            "echo 1.0.0 ${HOME}"
+        );
+        assert_eq!(fs::read(root.join("dist/data.bin"))?, b"${version}\0");
+        Ok(())
+    }
+
+    #[test]
+    // 测试比较两个目录时列出新增、删除和修改的文件
+    fn test_diff_dirs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("old/sub"))?;
+        fs::create_dir_all(root.join("new/sub"))?;
+        fs::write(root.join("old/same.txt"), "same")?;
+        fs::write(root.join("new/same.txt"), "same")?;
+        fs::write(root.join("old/sub/changed.txt"), "old")?;
+        fs::write(root.join("new/sub/changed.txt"), "new")?;
+        fs::write(root.join("old/removed.txt"), "")?;
+        fs::write(root.join("new/sub/added.txt"), "")?;
+
+        let diff = diff_dirs(&root.join("new"), &root.join("old"))?;
+        assert_eq!(
+            diff,
+            DirDiff {
+                added: vec!["sub/added.txt".to_string()],
+                removed: vec!["removed.txt".to_string()],
+                modified: vec!["sub/changed.txt".to_string()],
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    // 测试按校验和文件检查文件，一次性报告所有不匹配和缺失的文件
+    fn test_verify_checksums() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::write(root.join("a.txt"), "a")?;
+        fs::write(root.join("b.txt"), "b")?;
+        let checksums_file = root.join("SHA256SUMS");
+        fs::write(
+            &checksums_file,
+            "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb  a.txt\n\
+             3e23e8160039594a33894f6564e1b1348bbd7a0088d42c4acb73eeaed59c009d *b.txt\n",
+        )?;
+
+        let verify = VerifyChecksums {
+            checksums_file: checksums_file.display().to_string(),
+            base_dir: root.display().to_string(),
+        };
+        execute_verify_checksums(&verify)?;
+
+        fs::write(root.join("a.txt"), "changed")?;
+        fs::remove_file(root.join("b.txt"))?;
+        let error = execute_verify_checksums(&verify).unwrap_err().to_string();
+        assert!(error.contains("2 of 2"));
+        assert!(error.contains("a.txt") && error.contains("b.txt"));
+        Ok(())
+    }
+
+    #[test]
+    // 测试以硬链接和符号链接代替复制
+    fn test_copy_link() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("src/a.txt"), "a")?;
+
+        for (link, destination) in [(LinkMode::Hard, "hard"), (LinkMode::Symbolic, "symbolic")] {
+            let copy = Copy {
+                source: format!("{}/src", root.display()),
+                destination: format!("{}/{}", root.display(), destination),
+                gitignore_path: ".gitignore".to_string(),
+                link: Some(link),
+                ..Default::default()
+            };
+            // 第二次执行时覆盖已存在的链接
+            execute_copy(&copy, &ExecuteOptions::default())?;
+            execute_copy(&copy, &ExecuteOptions::default())?;
+        }
+        assert!(fs::symlink_metadata(root.join("symbolic/a.txt"))?.is_symlink());
+
+        // 链接与源文件共享内容
+        fs::write(root.join("src/a.txt"), "changed")?;
+        assert_eq!(fs::read_to_string(root.join("hard/a.txt"))?, "changed");
+        assert_eq!(fs::read_to_string(root.join("symbolic/a.txt"))?, "changed");
+        Ok(())
+    }
+
+    #[test]
+    // 测试部分文件复制失败时继续复制其余的文件，并汇总所有失败的文件
+    fn test_copy_collects_errors() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("src"))?;
+        fs::create_dir_all(root.join("dist"))?;
+        fs::write(root.join("src/a.txt"), "a")?;
+        fs::write(root.join("src/b.txt"), "b")?;
+        fs::write(root.join("src/c.txt"), "c")?;
+        // 目标路径已经是目录，复制会失败
+        fs::create_dir_all(root.join("dist/a.txt"))?;
+        fs::create_dir_all(root.join("dist/c.txt"))?;
+
+        let copy = Copy {
+            source: format!("{}/src", root.display()),
+            destination: format!("{}/dist", root.display()),
+            gitignore_path: ".gitignore".to_string(),
+            ..Default::default()
+        };
+        let error = execute_copy(&copy, &ExecuteOptions::default()).unwrap_err();
+        assert!(error.to_string().starts_with("2 file(s) failed to copy"));
+        assert_eq!(fs::read_to_string(root.join("dist/b.txt"))?, "b");
+
+        fs::remove_file(root.join("dist/b.txt"))?;
+        let options = ExecuteOptions {
+            fail_fast: true,
+            ..Default::default()
+        };
+        assert!(execute_copy(&copy, &options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试按内容类型筛选要复制的文件
+    fn test_copy_text_only() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("src/README"), "text")?;
+        fs::write(root.join("src/logo.png"), b"\x89PNG\0\0")?;
+
+        let copy = Copy {
+            source: format!("{}/src", root.display()),
+            destination: format!("{}/text", root.display()),
+            gitignore_path: ".gitignore".to_string(),
+            text_only: true,
+            ..Default::default()
+        };
+        execute_copy(&copy, &ExecuteOptions::default())?;
+        assert!(root.join("text/README").is_file());
+        assert!(!root.join("text/logo.png").exists());
+
+        let copy = Copy {
+            destination: format!("{}/binary", root.display()),
+            text_only: false,
+            binary_only: true,
+            ..copy
+        };
+        execute_copy(&copy, &ExecuteOptions::default())?;
+        assert!(!root.join("binary/README").exists());
+        assert!(root.join("binary/logo.png").is_file());
+        Ok(())
+    }
+
+    #[test]
+    // 测试全局排除规则对copy命令生效
+    fn test_copy_global_exclude() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/docs"))?;
+        fs::create_dir_all(root.join("src/cache"))?;
+        fs::write(root.join("src/main.txt"), "main")?;
+        fs::write(root.join("src/main.txt~"), "backup")?;
+        fs::write(root.join("src/docs/.DS_Store"), "cruft")?;
+        fs::write(root.join("src/cache/data"), "cache")?;
+
+        let copy = Copy {
+            source: format!("{}/src", root.display()),
+            destination: format!("{}/dist", root.display()),
+            gitignore_path: ".gitignore".to_string(),
+            ..Default::default()
+        };
+        let options = ExecuteOptions {
+            global_exclude: vec![
+                ".DS_Store".to_string(),
+                "*~".to_string(),
+                "cache".to_string(),
+            ],
+            ..Default::default()
+        };
+        execute_copy(&copy, &options)?;
+        assert!(root.join("dist/main.txt").is_file());
+        assert!(!root.join("dist/main.txt~").exists());
+        assert!(!root.join("dist/docs/.DS_Store").exists());
+        assert!(!root.join("dist/cache").exists());
+        Ok(())
+    }
+
+    #[test]
+    // 测试生成的JSON Schema包含配置的字段和所有命令类型
+    fn test_config_schema() -> Result<()> {
+        let schema: serde_json::Value = serde_json::from_str(&config_schema_json()?)?;
+        assert!(schema["properties"]["command"].is_object());
+        let command_item = schema["$defs"]["CommandItem"].to_string();
+        for kind in [
+            "Copy",
+            "Replace",
+            "Run",
+            "Custom",
+            "VerifyChecksums",
+            "Group",
+            "Assert",
+            "SetValue",
+            "List",
+            "ManifestVar",
+            "GenerateIndex",
+        ] {
+            assert!(command_item.contains(&format!("\"{}\"", kind)), "{}", kind);
+        }
+        Ok(())
+    }
+
+    #[test]
+    // 测试列出的命令类型和字段与结构体定义一致
+    fn test_list_commands_text() -> Result<()> {
+        let text = list_commands_text()?;
+        assert!(text.lines().any(|line| line == "Copy"));
+        assert!(text.lines().any(|line| line == "ManifestVar"));
+        assert!(text.contains("  source: string (required)"));
+        assert!(text.contains("  retries: integer (optional, default 0)"));
+        assert!(text.contains("  commands: list of Command (required)"));
+        assert!(text.contains("  link: \"Hard\" | \"Symbolic\" (optional)"));
+        Ok(())
+    }
+
+    #[test]
+    // 测试按环境名称选择overrides中的修改，并按命令名称或序号应用到命令上
+    fn test_overrides() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.yml");
+        fs::write(
+            &config_path,
+            "define_items:\n  - key: OUT\n    value: dist\ncommand:\n  - type: Copy\n    name: assets\n    source: assets\n    destination: out\n    gitignore_path: .gitignore\n    use_gitignore: false\n  - type: Run\n    command: echo\n    retries: 1\noverrides:\n  prod:\n    assets.destination: ${OUT}/prod\n    assets.copy_empty_dirs: 'true'\n    1.retries: '3'\n    1.command: '123'\n  typo:\n    assets.destinaton: x\n",
+        )?;
+        let path = config_path.display().to_string();
+        let config = parse_commands_from_yaml(&path, true, None, Some("prod"))?.0;
+        assert_eq!(config.overrides, None);
+        match &config.command[0].command {
+            Command::Copy(copy) => {
+                assert_eq!(
+                    copy.destination,
+                    Path::new("dist").join("prod").display().to_string()
+                );
+                assert!(copy.copy_empty_dirs);
+            }
+            _ => panic!("expected a copy command"),
+        }
+        match &config.command[1].command {
+            Command::Run(run) => {
+                assert_eq!(run.retries, 3);
+                assert_eq!(run.command, "123");
+            }
+            _ => panic!("expected a run command"),
+        }
+
+        // 不指定环境时不做修改
+        let config = parse_commands_from_yaml(&path, true, None, None)?.0;
+        match &config.command[0].command {
+            Command::Copy(copy) => assert_eq!(copy.destination, "out"),
+            _ => panic!("expected a copy command"),
+        }
+        assert!(parse_commands_from_yaml(&path, true, None, Some("dev")).is_err());
+        assert!(parse_commands_from_yaml(&path, true, None, Some("typo")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试通配符字段中引用的变量值被转义，标记为pattern的变量不转义
+    fn test_glob_variables_escaped() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.yml");
+        fs::write(
+            &config_path,
+            "define_items:\n  - key: VERSION\n    value: 1.0[beta]\n  - key: EXT\n    value: '*.txt'\n    pattern: true\n  - key: NAME\n    value: a{b}\nglobal_exclude:\n  - ${NAME}\ncommand:\n  - type: Copy\n    source: app-${VERSION}.zip\n    destination: out-${VERSION}\n    gitignore_path: .gitignore\n    use_gitignore: false\n  - type: Replace\n    source: docs${EXT}\n    regex: a\n    replacement: b\n",
+        )?;
+        let config =
+            parse_commands_from_yaml(&config_path.display().to_string(), true, None, None)?.0;
+        assert_eq!(config.global_exclude, Some(vec!["a[{]b[}]".to_string()]));
+        match &config.command[0].command {
+            Command::Copy(copy) => {
+                assert_eq!(copy.source, "app-1.0[[]beta[]].zip");
+                assert_eq!(copy.destination, "out-1.0[beta]");
+                fs::write(dir.path().join("app-1.0[beta].zip"), "")?;
+                fs::write(dir.path().join("app-1.0b.zip"), "")?;
+                let matched: Vec<PathBuf> =
+                    glob(&dir.path().join(&copy.source).display().to_string())?
+                        .collect::<Result<_, _>>()?;
+                assert_eq!(matched, vec![dir.path().join("app-1.0[beta].zip")]);
+            }
+            _ => panic!("expected a copy command"),
+        }
+        match &config.command[1].command {
+            Command::Replace(replace) => assert_eq!(replace.source, "docs*.txt"),
+            _ => panic!("expected a replace command"),
+        }
+
+        // 路径中转义的部分按字面计入基准路径，复制目录的结果与变量值不含特殊字符时相同
+        let app = dir.path().join("dist").join("app-1.0[beta]");
+        fs::create_dir_all(&app)?;
+        fs::write(app.join("x.txt"), "x")?;
+        fs::write(app.join("y.txt"), "y")?;
+        let escaped = dir
+            .path()
+            .join("dist")
+            .join(glob::Pattern::escape("app-1.0[beta]"));
+        assert_eq!(glob_base(&escaped.display().to_string()), app);
+        for (source, destination) in [(escaped.clone(), "out"), (escaped.join("*.txt"), "out2")] {
+            let destination = dir.path().join(destination);
+            execute_copy(
+                &Copy {
+                    source: source.display().to_string(),
+                    destination: destination.display().to_string(),
+                    ..Default::default()
+                },
+                &ExecuteOptions::default(),
+            )?;
+            assert!(destination.join("x.txt").is_file());
+            assert!(destination.join("y.txt").is_file());
+        }
+        Ok(())
+    }
+
+    #[test]
+    // 测试复制后比较目标文件与源文件，汇总所有大小或sha256不一致的文件
+    fn test_copy_verify() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("src");
+        fs::create_dir(&source)?;
+        fs::write(source.join("a.txt"), "hello")?;
+        fs::write(source.join("b.txt"), "world")?;
+        let destination = dir.path().join("dest");
+        let mut copy = Copy {
+            source: source.display().to_string(),
+            destination: destination.display().to_string(),
+            verify_hash: true,
+            ..Default::default()
+        };
+        execute_copy(&copy, &ExecuteOptions::default())?;
+
+        // 模拟截断和内容损坏
+        fs::write(destination.join("a.txt"), "hel")?;
+        fs::write(destination.join("b.txt"), "w0rld")?;
+        let transferred = vec![
+            (source.join("a.txt"), destination.join("a.txt")),
+            (source.join("b.txt"), destination.join("b.txt")),
+        ];
+        let error = verify_copied(&transferred, true).unwrap_err().to_string();
+        assert!(error.starts_with("2 file(s) failed verification"));
+        assert!(error.contains("size 3 differs from source size 5"));
+        assert!(error.contains("sha256 differs from source"));
+        let error = verify_copied(&transferred, false).unwrap_err().to_string();
+        assert!(error.starts_with("1 file(s) failed verification"));
+
+        copy.substitute = true;
+        assert!(execute_copy(&copy, &ExecuteOptions::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试按环境名称选择profile，其定义项优先于配置文件中的定义，并只保留列出的命令
+    fn test_profiles() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.yml");
+        fs::write(
+            &config_path,
+            "define_items:\n  - key: OUT\n    value: dev\ncommand:\n  - type: Run\n    name: build\n    command: echo ${OUT}\n  - type: Run\n    name: test\n    command: echo test\nprofiles:\n  prod:\n    define_items:\n      - key: OUT\n        value: release\n    commands: [build]\n  broken:\n    commands: [deploy]\n",
+        )?;
+        let path = config_path.display().to_string();
+        let run_commands = |config: &Config| -> Vec<String> {
+            config
+                .command
+                .iter()
+                .map(|item| match &item.command {
+                    Command::Run(run) => run.command.clone(),
+                    _ => panic!("expected a run command"),
+                })
+                .collect()
+        };
+        let config = parse_commands_from_yaml(&path, true, None, Some("prod"))?.0;
+        assert_eq!(config.profiles, None);
+        assert_eq!(run_commands(&config), vec!["echo release"]);
+        let config = parse_commands_from_yaml(&path, true, None, None)?.0;
+        assert_eq!(run_commands(&config), vec!["echo dev", "echo test"]);
+        assert!(parse_commands_from_yaml(&path, true, None, Some("staging")).is_err());
+        assert!(parse_commands_from_yaml(&path, true, None, Some("broken")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试物料清单按路径排序列出所有文件的大小和sha256，多个目录时路径前加上目录名
+    fn test_sbom_json() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dist = dir.path().join("dist");
+        fs::create_dir_all(dist.join("bin"))?;
+        fs::write(dist.join("readme.txt"), "abc")?;
+        fs::write(dist.join("bin").join("app"), "")?;
+        let docs = dir.path().join("docs");
+        fs::create_dir(&docs)?;
+        fs::write(docs.join("a.md"), "x")?;
+
+        let sbom: serde_json::Value =
+            serde_json::from_str(&sbom_json(&[("dist".to_string(), dist.clone())])?)?;
+        assert_eq!(
+            sbom,
+            serde_json::json!({ "files": [
+                {
+                    "path": "bin/app",
+                    "size": 0,
+                    "sha256": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+                },
+                {
+                    "path": "readme.txt",
+                    "size": 3,
+                    "sha256": "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+                }
+            ]})
+        );
+
+        let sbom: serde_json::Value = serde_json::from_str(&sbom_json(&[
+            ("dist/".to_string(), dist),
+            ("docs".to_string(), docs),
+        ])?)?;
+        let paths: Vec<&str> = sbom["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|file| file["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(paths, vec!["dist/bin/app", "dist/readme.txt", "docs/a.md"]);
+        Ok(())
+    }
+
+    #[test]
+    // 测试命令的log_level只在执行该命令期间生效，无效的级别使命令失败
+    fn test_command_log_level() {
+        let item = |log_level: Option<&str>| CommandItem {
+            command: Command::Run(Run {
+                command: "echo".to_string(),
+                ..Default::default()
+            }),
+            stage: None,
+            order: None,
+            optional: false,
+            parallel_group: None,
+            cwd: None,
+            name: None,
+            log_level: log_level.map(str::to_string),
+            written_destinations: None,
+        };
+        with_log_level(Some(LevelFilter::WARN), || {
+            assert_eq!(command_log_level(), Some(LevelFilter::WARN));
+            with_log_level(None, || {
+                assert_eq!(command_log_level(), Some(LevelFilter::WARN))
+            });
+            with_log_level(Some(LevelFilter::OFF), || {
+                assert_eq!(command_log_level(), Some(LevelFilter::OFF))
+            });
+        });
+        assert_eq!(command_log_level(), None);
+
+        let mut errors = Vec::new();
+        let commands = vec![item(Some("warn")), item(Some("loud"))];
+        execute_command_list(&commands, &ExecuteOptions::default(), &mut errors);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 1);
+        assert!(format!("{:#}", errors[0].1).contains("Invalid log_level"));
+
+        // 通过实际的日志输出检查：同一处日志在未设置级别的命令中输出，在设置了warn的命令中不输出
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+        impl Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(move || writer.clone())
+                .with_filter(CommandLogFilter(LevelFilter::TRACE)),
+        );
+        let assert_item = |log_level: Option<&str>| CommandItem {
+            command: Command::Assert(Assert {
+                path: ".".to_string(),
+                exists: true,
+                ..Default::default()
+            }),
+            ..item(log_level)
+        };
+        let commands = vec![
+            assert_item(None),
+            assert_item(Some("warn")),
+            assert_item(None),
+        ];
+        tracing::subscriber::with_default(subscriber, || {
+            execute_command_list(&commands, &ExecuteOptions::default(), &mut errors)
+        });
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.matches("*** Checking").count(), 2);
+        assert_eq!(output.matches("Assertion passed").count(), 2);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    // 测试按各种格式生成文件索引，html中的链接相对于索引文件所在的目录，并且不列出索引文件自己
+    fn test_generate_index() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let docs = dir.path().join("docs");
+        fs::create_dir_all(docs.join("sub"))?;
+        fs::write(docs.join("a b.txt"), "abc")?;
+        fs::write(docs.join("sub").join("<x>.md"), "")?;
+        let mut index = GenerateIndex {
+            directory: docs.display().to_string(),
+            output: docs.join("index.html").display().to_string(),
+            format: IndexFormat::Html,
+        };
+        execute_generate_index(&index)?;
+        // 再次生成时不列出上一次生成的索引
+        execute_generate_index(&index)?;
+        let html = fs::read_to_string(docs.join("index.html"))?;
+        assert!(html.contains("<li><a href=\"a%20b.txt\">a b.txt</a> (3 bytes)</li>"));
+        assert!(html.contains("<a href=\"sub/%3Cx%3E.md\">sub/&lt;x&gt;.md</a> (0 bytes)"));
+        assert!(!html.contains("index.html"));
+
+        index.output = dir
+            .path()
+            .join("site")
+            .join("index.html")
+            .display()
+            .to_string();
+        execute_generate_index(&index)?;
+        let html = fs::read_to_string(dir.path().join("site").join("index.html"))?;
+        assert!(html.contains("<a href=\"../docs/a%20b.txt\">"));
+
+        index.format = IndexFormat::Text;
+        index.output = dir.path().join("index.txt").display().to_string();
+        execute_generate_index(&index)?;
+        assert_eq!(
+            fs::read_to_string(&index.output)?,
+            "a b.txt\t3\nindex.html\t".to_string()
+                + &fs::metadata(docs.join("index.html"))?.len().to_string()
+                + "\nsub/<x>.md\t0\n"
+        );
+
+        index.format = IndexFormat::Json;
+        index.output = dir.path().join("index.json").display().to_string();
+        fs::remove_file(docs.join("index.html"))?;
+        execute_generate_index(&index)?;
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&index.output)?)?;
+        assert_eq!(
+            json,
+            serde_json::json!({ "files": [
+                { "path": "a b.txt", "size": 3 },
+                { "path": "sub/<x>.md", "size": 0 }
+            ]})
+        );
+        Ok(())
+    }
+
+    #[test]
+    // 测试define_items写成映射与写成列表时得到相同的变量，映射中重复的变量名报错
+    fn test_define_items_map() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        // 内置变量中包含配置文件名，因此两种写法使用同名的文件
+        fs::create_dir(dir.path().join("list"))?;
+        fs::create_dir(dir.path().join("map"))?;
+        let list_path = dir.path().join("list").join("config.yml");
+        let map_path = dir.path().join("map").join("config.yml");
+        fs::write(
+            &list_path,
+            "define_items:\n  - key: name\n    value: app\n  - key: version\n    value: '1.0'\n  - key: debug\n    value: 'false'\ncommand: []\n",
+        )?;
+        fs::write(
+            &map_path,
+            "define_items:\n  name: app\n  version: 1.0\n  debug: false\ncommand: []\n",
+        )?;
+        let variables = |path: &Path| -> Result<HashMap<String, String>> {
+            let mut variables =
+                parse_commands_from_yaml(&path.display().to_string(), true, None, None)?.1;
+            variables.remove("__CONFIG_DIR__");
+            Ok(variables)
+        };
+        let map_variables = variables(&map_path)?;
+        assert_eq!(map_variables, variables(&list_path)?);
+        assert_eq!(map_variables["version"], "1.0");
+
+        let config: Config = toml::from_str("[define_items]\nname = \"app\"\n")?;
+        assert_eq!(config.define_items[0].key, "name");
+        assert!(deserialize_config("define_items:\n  name: a\n  name: b\n").is_err());
+        assert!(deserialize_config("define_items:\n  name: [a]\n").is_err());
+        assert!(deserialize_config("define_items:\ncommand: []\n")?
+            .define_items
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    // 测试复制之前检查目标路径的可用空间，不足时不复制任何文件
+    fn test_copy_min_free_bytes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("src");
+        fs::create_dir(&source)?;
+        fs::write(source.join("a.txt"), "hello")?;
+        let destination = dir.path().join("out").join("nested");
+        let mut copy = Copy {
+            source: source.display().to_string(),
+            destination: destination.display().to_string(),
+            min_free_bytes: Some(u64::MAX),
+            ..Default::default()
+        };
+        let error = execute_copy(&copy, &ExecuteOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("less than min_free_bytes"));
+        assert!(!destination.exists());
+
+        // 命令中的设置优先于全局的设置
+        copy.min_free_bytes = Some(1);
+        let options = ExecuteOptions {
+            min_free_bytes: Some(u64::MAX),
+            ..Default::default()
+        };
+        execute_copy(&copy, &options)?;
+        assert_eq!(fs::read_to_string(destination.join("a.txt"))?, "hello");
+
+        copy.min_free_bytes = None;
+        assert!(execute_copy(&copy, &options).is_err());
+
+        // 估计需要的空间时与复制时一样按内容类型筛选文件
+        fs::write(source.join("b.bin"), [0u8; 100])?;
+        let measure = |copy: &Copy| -> Result<u64> {
+            let variables = HashMap::new();
+            let options = ExecuteOptions::default();
+            let mut context = CopyContext::new(copy, &options, &variables)?;
+            context.mode = WalkMode::Measure;
+            walk_source(&mut context)?;
+            Ok(context.bytes)
+        };
+        copy.text_only = true;
+        assert_eq!(measure(&copy)?, 5);
+        copy.text_only = false;
+        copy.binary_only = true;
+        assert_eq!(measure(&copy)?, 100);
+        Ok(())
+    }
+
+    #[test]
+    // 测试生成重新执行出错命令的命令行，替换原来的--continue-from和--stage
+    fn test_rerun_command_line() {
+        let args: Vec<String> = [
+            "autopackager",
+            "-c",
+            "my config.yml",
+            "--continue-from",
+            "2",
+            "-s",
+            "build",
+            "--only-stage",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        assert_eq!(
+            rerun_command_line(&args, Rerun::ContinueFrom(4)),
+            "autopackager -c \"my config.yml\" -s build --only-stage --continue-from 4"
+        );
+        assert_eq!(
+            rerun_command_line(&args, Rerun::Stage("test")),
+            "autopackager -c \"my config.yml\" --stage test"
+        );
+        let args: Vec<String> = ["autopackager", "--stage=build", "--continue-from=1"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(
+            rerun_command_line(&args, Rerun::ContinueFrom(3)),
+            "autopackager --stage=build --continue-from 3"
+        );
+        assert_eq!(
+            rerun_command_line(&args, Rerun::Stage("deploy")),
+            "autopackager --stage deploy"
+        );
+    }
+
+    #[test]
+    // 测试复制后在目标路径下的空目录中创建.gitkeep，非空目录不受影响
+    fn test_copy_gitkeep_empty_dirs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("src");
+        fs::create_dir_all(source.join("assets").join("empty"))?;
+        fs::create_dir_all(source.join("uploads"))?;
+        fs::write(source.join("assets").join("a.css"), "")?;
+        let destination = dir.path().join("dest");
+        fs::create_dir_all(destination.join("old"))?;
+        let copy = Copy {
+            source: source.display().to_string(),
+            destination: destination.display().to_string(),
+            copy_empty_dirs: true,
+            gitkeep_empty_dirs: true,
+            ..Default::default()
+        };
+        execute_copy(&copy, &ExecuteOptions::default())?;
+        assert!(destination.join("assets/empty/.gitkeep").is_file());
+        assert!(destination.join("uploads/.gitkeep").is_file());
+        assert!(destination.join("old/.gitkeep").is_file());
+        assert!(!destination.join("assets/.gitkeep").exists());
+        assert!(!destination.join(".gitkeep").exists());
+        Ok(())
+    }
+
+    #[test]
+    // 测试命令中的相对路径转换为相对于根目录的绝对路径
+    fn test_apply_base_dir() {
+        let base_dir = env::temp_dir().join("project");
+        let absolute = env::temp_dir().join("elsewhere").display().to_string();
+        let mut commands = vec![CommandItem {
+            command: Command::Group(Group {
+                commands: vec![
+                    Command::Copy(Copy {
+                        source: "build/**/*.wasm".to_string(),
+                        destination: absolute.clone(),
+                        ..Default::default()
+                    }),
+                    Command::Run(Run {
+                        command: "cat".to_string(),
+                        stdin_file: Some("input.txt".to_string()),
+                        ..Default::default()
+                    }),
+                ],
+                ..Default::default()
+            }),
+            stage: None,
+            order: None,
+            optional: false,
+            parallel_group: None,
+            cwd: None,
+            name: None,
+            log_level: None,
+            written_destinations: None,
+        }];
+        apply_base_dir(&mut commands, &base_dir);
+
+        let Command::Group(group) = &commands[0].command else {
+            panic!("expected a group");
+        };
+        let Command::Copy(copy) = &group.commands[0] else {
+            panic!("expected a copy");
+        };
+        assert_eq!(
+            copy.source,
+            base_dir.join("build/**/*.wasm").display().to_string()
+        );
+        assert_eq!(copy.destination, absolute);
+        let Command::Run(run) = &group.commands[1] else {
+            panic!("expected a run");
+        };
+        let expected = base_dir.join("input.txt").display().to_string();
+        assert_eq!(run.stdin_file.as_deref(), Some(expected.as_str()));
+    }
+
+    #[test]
+    // 测试前置条件检查
+    fn test_execute_assert() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("build"))?;
+        fs::write(root.join("build/app.wasm"), "wasm")?;
+
+        let assert = |path: &str, exists: bool, is_file: bool, is_dir: bool| {
+            execute_assert(&Assert {
+                path: format!("{}/{}", root.display(), path),
+                exists,
+                is_file,
+                is_dir,
+            })
+        };
+        assert!(assert("build", true, false, true).is_ok());
+        assert!(assert("build", true, true, false).is_err());
+        assert!(assert("build/*.wasm", true, true, false).is_ok());
+        assert!(assert("build/*.js", true, false, false).is_err());
+        assert!(assert("build/*.js", false, false, false).is_ok());
+        let error = assert("build/*.wasm", false, false, false).unwrap_err();
+        assert!(error.to_string().contains("app.wasm"));
+        assert!(assert("missing", false, false, false).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    // 测试并行替换多个文件，单个文件失败时其余文件仍然被处理，最后汇总报告
+    fn test_replace_parallel() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        for index in 0..20 {
+            fs::write(root.join(format!("{}.txt", index)), "Copyright 2023")?;
+        }
+        fs::write(root.join("broken.txt"), b"\xff\xfe")?;
+
+        let replace = Replace {
+            source: format!("{}/*.txt", root.display()),
+            regex: "2023".to_string(),
+            replacement: "2024".to_string(),
+            ..Default::default()
+        };
+        let options = ExecuteOptions {
+            jobs: Some(4),
+            profile: true,
+            ..Default::default()
+        };
+        let error = execute_replace(&replace, &options).unwrap_err();
+        assert!(error.to_string().starts_with("1 file(s) failed to replace"));
+        assert!(error.to_string().contains("broken.txt"));
+        for index in 0..20 {
+            let content = fs::read_to_string(root.join(format!("{}.txt", index)))?;
+            assert_eq!(content, "Copyright 2024");
+        }
+        let report = options.profile_report.lock().unwrap();
+        assert!(report[0].contains("21 file(s)"));
+        assert!(report[0].contains("20 replacement(s)"));
+        Ok(())
+    }
+
+    #[test]
+    // 测试从指定序号继续执行，并返回出错命令的序号和实际执行的命令数量
+    fn test_execute_commands_continue_from() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let marker = dir.path().join("marker");
+        let item = |command: Command| CommandItem {
+            command,
+            stage: None,
+            order: None,
+            optional: false,
+            parallel_group: None,
+            cwd: None,
+            name: None,
+            log_level: None,
+            written_destinations: None,
+        };
+        let commands = vec![
+            item(Command::Assert(Assert {
+                path: marker.display().to_string(),
+                exists: true,
+                ..Default::default()
+            })),
+            item(Command::Assert(Assert {
+                path: dir.path().display().to_string(),
+                exists: true,
+                ..Default::default()
+            })),
+            item(Command::Assert(Assert {
+                path: marker.display().to_string(),
+                exists: true,
+                ..Default::default()
+            })),
+        ];
+
+        let options = ExecuteOptions::default();
+        let errors = execute_commands(&commands, &options).unwrap_err();
+        let indices: Vec<usize> = errors.iter().map(|(index, _)| *index).collect();
+        assert_eq!(indices, vec![0, 2]);
+        assert_eq!(options.executed.load(Ordering::SeqCst), 3);
+
+        let options = ExecuteOptions {
+            continue_from: 1,
+            ..Default::default()
+        };
+        let errors = execute_commands(&commands, &options).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 2);
+        // 被跳过的命令不计入实际执行的数量
+        assert_eq!(options.executed.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[test]
+    // 测试用handlebars模板生成重复的命令
+    fn test_config_template() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::write(root.join("vars.yml"), "components:\n  - core\n  - ui\n")?;
+        fs::write(
+            root.join("config.yml.hbs"),
+            concat!(
+                "vars_file: vars.yml\n",
+                "define_items:\n",
+                "  - key: dist\n",
+                "    value: out\n",
+                "command:\n",
+                "{{#each components}}\n",
+                "  - type: Copy\n",
+                "    source: src/{{this}}\n",
+                "    destination: {{../dist}}/{{this}}-${dist}\n",
+                "    gitignore_path: .gitignore\n",
+                "    use_gitignore: false\n",
+                "{{/each}}\n",
+            ),
+        )?;
+
+        let config_path = root.join("config.yml.hbs").display().to_string();
+        let config = parse_commands_from_yaml(&config_path, true, None, None)?.0;
+        let destinations: Vec<&str> = config
+            .command
+            .iter()
+            .flat_map(|item| item.command.destinations())
+            .collect();
+        assert_eq!(destinations, vec!["out/core-out", "out/ui-out"]);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    // 测试复制时按规则设置文件权限
+    fn test_copy_modes() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/bin"))?;
+        fs::write(root.join("src/bin/run.sh"), "#!/bin/sh")?;
+        fs::write(root.join("src/data.txt"), "data")?;
+        fs::write(root.join("src/secret.key"), "key")?;
+        fs::set_permissions(root.join("src/data.txt"), fs::Permissions::from_mode(0o640))?;
+
+        let rule = |pattern: &str, mode: &str| ModeRule {
+            pattern: pattern.to_string(),
+            mode: mode.to_string(),
+        };
+        let copy = Copy {
+            source: format!("{}/src", root.display()),
+            destination: format!("{}/dist", root.display()),
+            gitignore_path: ".gitignore".to_string(),
+            modes: Some(vec![
+                rule("*.sh", "755"),
+                rule("*.key", "0o600"),
+                rule("*.sh", "700"),
+            ]),
+            ..Default::default()
+        };
+        execute_copy(&copy, &ExecuteOptions::default())?;
+        let mode = |path: &str| -> Result<u32> {
+            Ok(fs::metadata(root.join(path))?.permissions().mode() & 0o7777)
+        };
+        assert_eq!(mode("dist/bin/run.sh")?, 0o755);
+        assert_eq!(mode("dist/secret.key")?, 0o600);
+        assert_eq!(mode("dist/data.txt")?, 0o640);
+
+        let copy = Copy {
+            modes: Some(vec![rule("*", "999")]),
+            ..copy
+        };
+        assert!(execute_copy(&copy, &ExecuteOptions::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试截断日志中的命令输出
+    fn test_truncate_output() {
+        assert_eq!(truncate_output("hello", None), "hello");
+        assert_eq!(truncate_output("hello", Some(5)), "hello");
+        assert_eq!(
+            truncate_output("hello world", Some(5)),
+            "hello...[truncated 6 bytes]"
+        );
+        // 不在多字节字符的中间截断
+        assert_eq!(truncate_output("中文", Some(4)), "中...[truncated 3 bytes]");
+    }
+
+    #[test]
+    // 测试可选命令的失败不计入失败次数，也不会中止执行
+    fn test_optional_command() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let marker = dir.path().join("marker");
+        let commands = vec![
+            CommandItem {
+                command: Command::Assert(Assert {
+                    path: marker.display().to_string(),
+                    exists: true,
+                    ..Default::default()
+                }),
+                stage: None,
+                order: None,
+                optional: true,
+                parallel_group: None,
+                cwd: None,
+                name: None,
+                log_level: None,
+                written_destinations: None,
+            },
+            CommandItem {
+                command: Command::Run(Run {
+                    command: "echo done".to_string(),
+                    stdout_file: Some(marker.display().to_string()),
+                    ..Default::default()
+                }),
+                stage: None,
+                order: None,
+                optional: false,
+                parallel_group: None,
+                cwd: None,
+                name: None,
+                log_level: None,
+                written_destinations: None,
+            },
+        ];
+        let options = ExecuteOptions {
+            max_failures: Some(1),
+            fail_fast: true,
+            ..Default::default()
+        };
+        assert!(execute_commands(&commands, &options).is_ok());
+        assert!(marker.is_file());
+        Ok(())
+    }
+
+    #[test]
+    // 测试run命令的缓存键，只与命令和输入文件的内容有关
+    fn test_run_cache_key() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::write(root.join("a.proto"), "message A {}")?;
+        fs::write(root.join("b.proto"), "message B {}")?;
+        let files = vec![format!("{}/*.proto", root.display())];
+
+        let key = run_cache_key("protoc *.proto", &files)?;
+        assert_eq!(key, run_cache_key("protoc *.proto", &files)?);
+        assert_ne!(key, run_cache_key("protoc --fast *.proto", &files)?);
+        fs::write(root.join("b.proto"), "message B { int32 x = 1; }")?;
+        assert_ne!(key, run_cache_key("protoc *.proto", &files)?);
+
+        let missing = vec![root.join("missing").display().to_string()];
+        assert!(run_cache_key("protoc", &missing).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试以过滤器的方式替换输入流中的内容
+    fn test_replace_stream() -> Result<()> {
+        let replace = Replace {
+            source: STDIO_SOURCE.to_string(),
+            regex: r"v(\d+)".to_string(),
+            replacement: "version $1".to_string(),
+            ..Default::default()
+        };
+        let regex = ReplaceRegex::Text(vec![(
+            Regex::new(&replace.regex)?,
+            replace.replacement.clone(),
+        )]);
+        let mut output = Vec::new();
+        replace_stream(
+            &replace,
+            &regex,
+            &mut "v1\nv2\n".as_bytes(),
+            &mut output,
+            false,
+        )?;
+        assert_eq!(String::from_utf8(output)?, "version 1\nversion 2\n");
+
+        let mut output = Vec::new();
+        replace_stream(&replace, &regex, &mut "v1".as_bytes(), &mut output, true)?;
+        assert!(output.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    // 测试复制时给文件名添加前缀和后缀
+    fn test_copy_name_affixes() -> Result<()> {
+        assert_eq!(
+            add_name_affixes(Path::new("out/app.exe"), None, Some("-1.0")),
+            Path::new("out/app-1.0.exe")
+        );
+        assert_eq!(
+            add_name_affixes(Path::new("out/LICENSE"), Some("app-"), Some(".bak")),
+            Path::new("out/app-LICENSE.bak")
+        );
+
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/lib"))?;
+        fs::write(root.join("src/lib/core.dll"), "dll")?;
+        let copy = Copy {
+            source: format!("{}/src", root.display()),
+            destination: format!("{}/dist", root.display()),
+            gitignore_path: ".gitignore".to_string(),
+            add_suffix: Some("-2.1".to_string()),
+            ..Default::default()
+        };
+        execute_copy(&copy, &ExecuteOptions::default())?;
+        assert!(root.join("dist/lib/core-2.1.dll").is_file());
+        Ok(())
+    }
+
+    #[test]
+    // 测试修改json、yaml和toml文件中的值
+    fn test_execute_set_value() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        let set = |file: &str, key_path: &str, value: &str| {
+            execute_set_value(&SetValue {
+                path: root.join(file).display().to_string(),
+                key_path: key_path.to_string(),
+                value: value.to_string(),
+            })
+        };
+
+        fs::write(
+            root.join("package.json"),
+            "{\n  \"name\": \"app\",\n  \"version\": \"1.0.0\",\n  \"files\": [\"a\", \"b\"]\n}\n",
+        )?;
+        set("package.json", "version", "1.1.0")?;
+        set("package.json", "files.1", "c")?;
+        assert_eq!(
+            fs::read_to_string(root.join("package.json"))?,
+            "{\n  \"name\": \"app\",\n  \"version\": \"1.1.0\",\n  \"files\": [\"a\", \"c\"]\n}\n"
+        );
+
+        // json中其他行逐字节保持不变，包括缩进、键的顺序和末尾没有换行
+        let original = "{\n\t\"z\": {\"b\": 1,  \"a\": \"x}\"},\n\t\"version\" :\"1.0\",\n\t\"list\": [ {\"v\": 2} ]\n}";
+        fs::write(root.join("tabs.json"), original)?;
+        set("tabs.json", "version", "2.0")?;
+        set("tabs.json", "list.0.v", "3")?;
+        assert_eq!(
+            fs::read_to_string(root.join("tabs.json"))?,
+            original
+                .replace("\"1.0\"", "\"2.0\"")
+                .replace("\"v\": 2", "\"v\": \"3\"")
+        );
+        assert!(set("package.json", "files.5", "d").is_err());
+        assert!(set("package.json", "name.first", "d").is_err());
+
+        fs::write(root.join("app.yml"), "app:\n  version: 1.0.0\n")?;
+        set("app.yml", "app.build.number", "42")?;
+        assert_eq!(
+            fs::read_to_string(root.join("app.yml"))?,
+            "app:\n  version: 1.0.0\n  build:\n    number: '42'\n"
+        );
+
+        fs::write(
+            root.join("Cargo.toml"),
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n",
+        )?;
+        set("Cargo.toml", "package.version", "0.2.0")?;
+        assert_eq!(
+            fs::read_to_string(root.join("Cargo.toml"))?,
+            "[package]\nname = \"app\"\nversion = \"0.2.0\"\n"
+        );
+
+        // toml中的日期时间和注释保持原样，新的键添加到对应的表中
+        fs::write(
+            root.join("release.toml"),
+            "# release info\nreleased = 1979-05-27T07:32:00Z\n\n[app]\nversion = \"1.0\" # bumped by CI\n",
+        )?;
+        set("release.toml", "app.version", "1.1")?;
+        set("release.toml", "app.build.number", "42")?;
+        assert_eq!(
+            fs::read_to_string(root.join("release.toml"))?,
+            "# release info\nreleased = 1979-05-27T07:32:00Z\n\n[app]\nversion = \"1.1\" # bumped by CI\n\n[app.build]\nnumber = \"42\"\n"
+        );
+
+        // yaml中修改已有的值时保留注释和排版
+        fs::write(
+            root.join("p.yml"),
+            "# project settings\napp:\n  # current version\n  version: 1.0.0 # bumped by CI\n  name: app\n",
+        )?;
+        set("p.yml", "app.version", "1.1.0")?;
+        set("p.yml", "app.name", "01")?;
+        assert_eq!(
+            fs::read_to_string(root.join("p.yml"))?,
+            "# project settings\napp:\n  # current version\n  version: 1.1.0 # bumped by CI\n  name: '01'\n"
+        );
+        assert!(set("p.yml", "app.name.first", "d").is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    // 测试同一并行组的命令同时执行：两条命令互相等待对方创建的文件，依次执行时会超时失败
+    fn test_parallel_group() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().display().to_string();
+        let wait_for = |own: &str, other: &str| {
+            CommandItem {
+            command: Command::Run(Run {
+                command: format!(
+                    "sh -c 'touch {root}/{own}; for i in $(seq 50); do [ -f {root}/{other} ] && exit 0; sleep 0.1; done; exit 1'"
+                ),
+                ..Default::default()
+            }),
+            stage: None,
+            order: None,
+            optional: false,
+            parallel_group: Some("downloads".to_string()),
+            cwd: None,
+            name: None,
+            log_level: None,
+            written_destinations: None,
+        }
+        };
+        let commands = vec![
+            wait_for("a", "b"),
+            wait_for("b", "a"),
+            CommandItem {
+                command: Command::Assert(Assert {
+                    path: format!("{}/[ab]", root),
+                    exists: true,
+                    is_file: true,
+                    ..Default::default()
+                }),
+                stage: None,
+                order: None,
+                optional: false,
+                parallel_group: None,
+                cwd: None,
+                name: None,
+                log_level: None,
+                written_destinations: None,
+            },
+        ];
+        assert!(execute_commands(&commands, &ExecuteOptions::default()).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    // 测试允许源路径不存在
+    fn test_copy_allow_missing_source() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        let copy = Copy {
+            source: format!("{}/variant", root.display()),
+            destination: format!("{}/dist", root.display()),
+            gitignore_path: ".gitignore".to_string(),
+            ..Default::default()
+        };
+        assert!(execute_copy(&copy, &ExecuteOptions::default()).is_err());
+        let options = ExecuteOptions {
+            allow_missing_source: true,
+            ..Default::default()
+        };
+        execute_copy(&copy, &options)?;
+
+        let copy = Copy {
+            source: format!("{}/variant/*.dll", root.display()),
+            allow_missing_source: true,
+            ..copy
+        };
+        execute_copy(&copy, &ExecuteOptions::default())?;
+        assert!(!root.join("dist").exists());
+        Ok(())
+    }
+
+    #[test]
+    // 测试list命令只遍历源路径，不复制文件
+    fn test_execute_list() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("src/main.txt"), "main")?;
+        let list = List {
+            source: format!("{}/src", root.display()),
+            gitignore_path: ".gitignore".to_string(),
+            use_gitignore: true,
+        };
+        execute_list(&list, &ExecuteOptions::default())?;
+        assert_eq!(fs::read_dir(root)?.count(), 1);
+
+        let list = List {
+            source: format!("{}/missing", root.display()),
+            ..list
+        };
+        assert!(execute_list(&list, &ExecuteOptions::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试导出配置时去掉定义项并隐藏敏感变量的值
+    fn test_export_config_yaml() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.yml");
+        fs::write(
+            &config_path,
+            "define_items:\n  - key: token\n    value: s3cr3t\n    secret: true\n  - key: name\n    value: app\ncommand:\n  - type: Run\n    command: \"upload ${name} --token=${token}\"\n",
+        )?;
+        let (config, variables) =
+            parse_commands_from_yaml(&config_path.display().to_string(), true, None, None)?;
+        let yaml = export_config_yaml(&config, &variables)?;
+        assert!(!yaml.contains("s3cr3t"));
+        assert!(!yaml.contains("define_items"));
+        assert!(yaml.contains("upload app --token=***"));
+
+        // 导出的配置可以再次解析
+        let exported: Config = serde_yaml::from_str(&yaml)?;
+        assert!(exported.define_items.is_empty());
+        assert_eq!(exported.command.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    // 测试统一路径分隔符，含有通配符的路径保持不变
+    fn test_normalize_paths() -> Result<()> {
+        let native = |parts: &[&str]| parts.join(std::path::MAIN_SEPARATOR_STR);
+        assert_eq!(
+            normalize_separators("build\\out/bin"),
+            native(&["build", "out", "bin"])
+        );
+        assert_eq!(
+            normalize_separators("build/out\\"),
+            native(&["build", "out", ""])
+        );
+        assert_eq!(normalize_separators("src\\*.txt"), "src\\*.txt");
+
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.yml");
+        fs::write(
+            &config_path,
+            "command:\n  - type: Group\n    commands:\n      - type: Copy\n        source: 'build\\out'\n        destination: dist/app\n        gitignore_path: 'conf/.gitignore'\n        use_gitignore: true\n",
+        )?;
+        let config =
+            parse_commands_from_yaml(&config_path.display().to_string(), true, None, None)?.0;
+        match &config.command[0].command {
+            Command::Group(group) => match &group.commands[0] {
+                Command::Copy(copy) => {
+                    assert_eq!(copy.source, native(&["build", "out"]));
+                    assert_eq!(copy.destination, native(&["dist", "app"]));
+                    assert_eq!(copy.gitignore_path, native(&["conf", ".gitignore"]));
+                }
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    // 测试使用指定大小的缓冲区复制文件
+    fn test_copy_buffer_bytes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("src");
+        fs::create_dir(&source)?;
+        let content = (0..10000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        fs::write(source.join("data.bin"), &content)?;
+        let destination = dir.path().join("dest");
+        fs::create_dir(&destination)?;
+        fs::write(
+            destination.join("data.bin"),
+            "old content that is overwritten",
+        )?;
+
+        let mut copy = Copy {
+            source: source.display().to_string(),
+            destination: destination.display().to_string(),
+            copy_buffer_bytes: Some(7),
+            ..Default::default()
+        };
+        execute_copy(&copy, &ExecuteOptions::default())?;
+        assert_eq!(fs::read(destination.join("data.bin"))?, content);
+
+        copy.copy_buffer_bytes = Some(0);
+        assert!(execute_copy(&copy, &ExecuteOptions::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试对matrix中的每一组变量分别执行全部命令
+    fn test_matrix() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.yml");
+        let output = dir.path().join("out.txt");
+        fs::write(
+            &config_path,
+            format!(
+                "matrix:\n  - arch: x64\n  - arch: arm64\ndefine_items:\n  - key: arch\n    value: ignored\n  - key: name\n    value: app-${{arch}}\ncommand:\n  - type: Run\n    command: \"echo ${{name}}\"\n    stdout_file: '{}'\n",
+                output.display()
+            ),
+        )?;
+        let (config, variables) =
+            parse_commands_from_yaml(&config_path.display().to_string(), true, None, None)?;
+        let options = ExecuteOptions {
+            variables,
+            matrix: config.matrix.clone().unwrap_or_default(),
+            ..Default::default()
+        };
+        execute_commands(&config.command, &options).map_err(|e| anyhow!("{:?}", e))?;
+        // 最后执行的是第二组变量
+        assert_eq!(fs::read_to_string(&output)?.trim(), "app-arm64");
+        assert_eq!(options.captured_variables()["arch"], "arm64");
+        Ok(())
+    }
+
+    #[test]
+    // 测试执行时替换matrix中的变量：通配符字段中的值经过转义，转义的$${NAME}不会被展开
+    fn test_matrix_escapes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("dist").join("app-1.0[beta]"))?;
+        fs::create_dir_all(root.join("dist").join("app-1.0b"))?;
+        fs::write(root.join("dist").join("app-1.0[beta]").join("a.txt"), "a")?;
+        fs::write(root.join("dist").join("app-1.0[beta]").join("b.txt"), "b")?;
+        fs::write(root.join("dist").join("app-1.0b").join("c.txt"), "c")?;
+        fs::write(root.join("info.json"), "{}\n")?;
+        let config_path = root.join("config.yml");
+        fs::write(
+            &config_path,
+            format!(
+                "matrix:\n  - ver: 1.0[beta]\ncommand:\n  - type: Copy\n    source: '{root}/dist/app-${{ver}}/*.txt'\n    destination: '{root}/out/${{ver}}'\n    gitignore_path: .gitignore\n    use_gitignore: false\n  - type: SetValue\n    path: '{root}/info.json'\n    key_path: note\n    value: $${{ver}} is ${{ver}}\n",
+                root = root.display()
+            ),
+        )?;
+        let (config, variables) =
+            parse_commands_from_yaml(&config_path.display().to_string(), true, None, None)?;
+        let options = ExecuteOptions {
+            variables,
+            matrix: config.matrix.clone().unwrap_or_default(),
+            ..Default::default()
+        };
+        execute_commands(&config.command, &options).map_err(|e| anyhow!("{:?}", e))?;
+        let out = root.join("out").join("1.0[beta]");
+        assert!(out.join("a.txt").is_file());
+        assert!(out.join("b.txt").is_file());
+        assert!(!out.join("c.txt").exists());
+        let info: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(root.join("info.json"))?)?;
+        assert_eq!(info["note"], "${ver} is 1.0[beta]");
+        Ok(())
+    }
+
+    #[test]
+    // 测试replace的替换内容中对正则分组的引用不作为找不到定义的变量警告
+    fn test_unresolved_variables_skip_regex_groups() -> Result<()> {
+        let config = deserialize_config(concat!(
+            "command:\n",
+            "  - type: Replace\n",
+            "    source: a.txt\n",
+            "    regex: '(?P<major>\\d+)\\.(\\d+)'\n",
+            "    replacement: '${major}.${2}-${TYPO}'\n",
+            "  - type: Replace\n",
+            "    source: b.txt\n",
+            "    replacements:\n",
+            "      - regex: '(?<name>\\w+)=(\\w+)'\n",
+            "        replacement: '${name}:${1}'\n",
+            "  - type: Run\n",
+            "    command: echo ${name}\n",
+        ))?;
+        let unresolved = ["major", "2", "TYPO", "name", "1"]
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        // name同时出现在run命令中，但它已经作为分组名出现在替换内容中，同样不警告
+        assert_eq!(unresolved_variables(&config, unresolved), vec!["TYPO"]);
+        Ok(())
+    }
+
+    #[test]
+    // 测试执行之前检查run命令的可执行文件
+    fn test_check_commands() {
+        let item = |command: &str, use_shell_fallback: Option<bool>| CommandItem {
+            command: Command::Run(Run {
+                command: command.to_string(),
+                use_shell_fallback,
+                ..Default::default()
+            }),
+            stage: None,
+            order: None,
+            optional: false,
+            parallel_group: None,
+            cwd: None,
+            name: None,
+            log_level: None,
+            written_destinations: None,
+        };
+        let commands = vec![
+            item("cargo --version", Some(false)),
+            item("no-such-tool-autopackager --version", Some(false)),
+            // 允许回退到shell的命令找不到时只输出警告
+            item("no-such-tool-autopackager", None),
+            item("${tool} --version", Some(false)),
+        ];
+        let errors = check_commands(&commands);
+        assert_eq!(errors.len(), 1);
+        assert!(format!("{:#}", errors[0]).starts_with("command #1 (Run)"));
+    }
+
+    #[test]
+    // 测试按指定的编码读取、替换并写回文件
+    fn test_replace_encoding() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let utf16_path = dir.path().join("version.rc");
+        let utf16 = [0xFF, 0xFE]
+            .into_iter()
+            .chain("版本 1.0\r\n".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect::<Vec<_>>();
+        fs::write(&utf16_path, utf16)?;
+        let latin1_path = dir.path().join("readme.txt");
+        fs::write(&latin1_path, b"caf\xe9 1.0\n")?;
+
+        let mut replace = Replace {
+            source: utf16_path.display().to_string(),
+            regex: r"\d+\.\d+".to_string(),
+            replacement: "2.0".to_string(),
+            encoding: Some("utf-16le".to_string()),
+            ..Default::default()
+        };
+        execute_replace(&replace, &ExecuteOptions::default())?;
+        let expected = [0xFF, 0xFE]
+            .into_iter()
+            .chain("版本 2.0\r\n".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect::<Vec<_>>();
+        assert_eq!(fs::read(&utf16_path)?, expected);
+
+        replace.source = latin1_path.display().to_string();
+        replace.encoding = Some("latin1".to_string());
+        execute_replace(&replace, &ExecuteOptions::default())?;
+        assert_eq!(fs::read(&latin1_path)?, b"caf\xe9 2.0\n");
+
+        replace.encoding = Some("no-such-encoding".to_string());
+        assert!(execute_replace(&replace, &ExecuteOptions::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试输出执行计划，并行组中的命令列为一批
+    fn test_plan() {
+        let item = |command: &str, stage: Option<&str>, parallel_group: Option<&str>| CommandItem {
+            command: Command::Run(Run {
+                command: command.to_string(),
+                ..Default::default()
+            }),
+            stage: stage.map(str::to_string),
+            order: None,
+            optional: false,
+            parallel_group: parallel_group.map(str::to_string),
+            cwd: None,
+            name: None,
+            log_level: None,
+            written_destinations: None,
+        };
+        let commands = vec![
+            item("make", Some("build"), None),
+            item("test a", Some("test"), Some("tests")),
+            item("test \"b\"", Some("test"), Some("tests")),
+            item("pack", None, None),
+        ];
+
+        let text = plan_text(&commands);
+        assert!(text.starts_with("Step #0 [build]: "));
+        assert!(text.contains("Parallel group tests:\n  Step #1 [test]: "));
+        assert!(text.contains("\n  Step #2 [test]: "));
+        assert!(text.contains("\nStep #3: "));
+
+        let dot = plan_dot(&commands);
+        assert!(dot.starts_with("digraph plan {"));
+        assert!(dot.contains("label=\"parallel group tests\";\n        n1; n2;"));
+        assert!(dot.contains("n0 -> n1;\n    n0 -> n2;"));
+        assert!(dot.contains("n1 -> n3;\n    n2 -> n3;"));
+        assert!(dot.contains("test \\\"b\\\""));
+    }
+
+    #[test]
+    // 测试命令中的相对路径相对于cwd解析，run命令在cwd下执行
+    fn test_command_cwd() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.yml");
+        let absolute = dir.path().join("abs").display().to_string();
+        fs::write(
+            &config_path,
+            format!("command:\n  - type: Copy\n    source: src\n    destination: {}\n    gitignore_path: .gitignore\n    use_gitignore: false\n    cwd: sub\n  - type: Run\n    command: pwd\n    stdout_file: pwd.txt\n    cwd: sub\n", absolute),
+        )?;
+        let config =
+            parse_commands_from_yaml(&config_path.display().to_string(), true, None, None)?.0;
+        assert_eq!(config.command[0].cwd, None);
+        match &config.command[0].command {
+            Command::Copy(copy) => {
+                assert_eq!(
+                    copy.source,
+                    Path::new("sub").join("src").display().to_string()
+                );
+                assert_eq!(copy.destination, absolute);
+            }
+            _ => unreachable!(),
+        }
+
+        // run命令的工作路径同样经过--base-dir转换为绝对路径
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub)?;
+        let mut commands = config.command;
+        apply_base_dir(&mut commands[1..], dir.path());
+        match &commands[1].command {
+            Command::Run(run) => {
+                assert_eq!(run.cwd.as_deref(), Some(sub.display().to_string().as_str()));
+                if cfg!(unix) {
+                    execute_run(run, &ExecuteOptions::default())?;
+                    let pwd = fs::read_to_string(sub.join("pwd.txt"))?;
+                    assert_eq!(Path::new(pwd.trim()).canonicalize()?, sub.canonicalize()?);
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    // 测试--strict-paths下拒绝绝对路径和含有..的目标路径
+    fn test_check_strict_paths() -> Result<()> {
+        let item = |destination: String| CommandItem {
+            command: Command::Copy(Copy {
+                source: "src".to_string(),
+                destination,
+                ..Default::default()
+            }),
+            stage: None,
+            order: None,
+            optional: false,
+            parallel_group: None,
+            cwd: None,
+            name: None,
+            log_level: None,
+            written_destinations: None,
+        };
+        let absolute = std::env::temp_dir().join("out").display().to_string();
+        let commands = vec![
+            item("dist/app".to_string()),
+            item(absolute.clone()),
+            item("dist/../../outside".to_string()),
+        ];
+        let errors = check_strict_paths(&commands);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].to_string().starts_with("command #1 (Copy)"));
+        assert!(errors[1].to_string().contains("contains .."));
+
+        // 设置了cwd时检查的是配置中写下的路径，而不是拼接cwd之后的路径
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.yml");
+        let copy = |destination: &str, cwd: &str| {
+            format!(
+                "  - type: Copy\n    source: src\n    destination: '{}'\n    gitignore_path: .gitignore\n    use_gitignore: false\n    cwd: '{}'\n",
+                destination, cwd
+            )
+        };
+        let root = dir.path().display().to_string();
+        fs::write(
+            &config_path,
+            format!(
+                "command:\n{}{}{}",
+                copy("dist", &root),
+                copy("../outside", "sub/dir"),
+                copy(&absolute, &root),
+            ),
+        )?;
+        let config =
+            parse_commands_from_yaml(&config_path.display().to_string(), true, None, None)?.0;
+        let errors = check_strict_paths(&config.command);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].to_string().starts_with("command #1 (Copy)"));
+        assert!(errors[0].to_string().contains("../outside contains .."));
+        assert!(errors[1].to_string().starts_with("command #2 (Copy)"));
+        assert!(errors[1].to_string().contains("is absolute"));
+        Ok(())
+    }
+
+    #[test]
+    // 测试在一条replace命令中按顺序应用多条替换规则
+    fn test_replace_multiple_rules() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("about.txt");
+        fs::write(&path, "name=old\nversion=0.1\n")?;
+        let rule = |regex: &str, replacement: &str| ReplaceRule {
+            regex: regex.to_string(),
+            replacement: replacement.to_string(),
+        };
+        let mut replace = Replace {
+            source: path.display().to_string(),
+            replacements: Some(vec![
+                rule(r"name=\w+", "name=app"),
+                rule(r"version=[\d.]+", "version=1.0"),
+                // 作用于前一条规则替换后的内容
+                rule(r"app", "App"),
+            ]),
+            ..Default::default()
+        };
+        execute_replace(&replace, &ExecuteOptions::default())?;
+        assert_eq!(fs::read_to_string(&path)?, "name=App\nversion=1.0\n");
+
+        replace.regex = "name".to_string();
+        assert!(execute_replace(&replace, &ExecuteOptions::default()).is_err());
+
+        // 既没有regex也没有规则时报错，文件保持不变
+        for replacements in [None, Some(Vec::new())] {
+            let replace = Replace {
+                source: path.display().to_string(),
+                replacement: "x".to_string(),
+                replacements,
+                ..Default::default()
+            };
+            let error = execute_replace(&replace, &ExecuteOptions::default()).unwrap_err();
+            assert!(error.to_string().contains("replacements"));
+            assert_eq!(fs::read_to_string(&path)?, "name=App\nversion=1.0\n");
+        }
+        Ok(())
+    }
+
+    #[test]
+    // 测试统计目录内容并保存为变量，之后的命令可以引用
+    fn test_manifest_var() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let package = dir.path().join("package");
+        fs::create_dir_all(package.join("lib"))?;
+        fs::write(package.join("app"), "12345")?;
+        fs::write(package.join("lib").join("core"), "123")?;
+        let info = dir.path().join("info.json");
+
+        let item = |command: Command| CommandItem {
+            command,
+            stage: None,
+            order: None,
+            optional: false,
+            parallel_group: None,
+            cwd: None,
+            name: None,
+            log_level: None,
+            written_destinations: None,
+        };
+        let commands = vec![
+            item(Command::ManifestVar(ManifestVar {
+                directory: package.display().to_string(),
+                into: "MANIFEST".to_string(),
+            })),
+            item(Command::SetValue(SetValue {
+                path: info.display().to_string(),
+                key_path: "manifest".to_string(),
+                value: "${MANIFEST}".to_string(),
+            })),
+        ];
+        fs::write(&info, "{}\n")?;
+        let options = ExecuteOptions::default();
+        execute_commands(&commands, &options).map_err(|e| anyhow!("{:?}", e))?;
+        let summary: serde_json::Value =
+            serde_json::from_str(&options.captured_variables()["MANIFEST"])?;
+        assert_eq!(summary, serde_json::json!({ "files": 2, "total_bytes": 8 }));
+        let info: serde_json::Value = serde_json::from_str(&fs::read_to_string(&info)?)?;
+        assert_eq!(info["manifest"], summary.to_string());
+        Ok(())
+    }
+
+    #[test]
+    // 测试条件表达式的求值
+    fn test_evaluate_condition() -> Result<()> {
+        assert!(evaluate_condition("main == main")?);
+        assert!(evaluate_condition(" 'main' == \"main\" ")?);
+        assert!(!evaluate_condition("feature/x == main")?);
+        assert!(evaluate_condition("feature/x != main")?);
+        assert!(evaluate_condition("!false")?);
+        assert!(!evaluate_condition("")?);
+        assert!(evaluate_condition("release == main || release == release")?);
+        assert!(!evaluate_condition("a == a && b == c")?);
+        assert!(evaluate_condition("YES && 1 && !0")?);
+        assert!(evaluate_condition("${BRANCH}").is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试复制失败的错误信息中包含源路径和目标路径
+    fn test_copy_error_context() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("src");
+        fs::create_dir_all(source.join("sub"))?;
+        fs::write(source.join("sub").join("a.txt"), "a")?;
+        // 目标路径中应为目录的位置已经是一个文件
+        let destination = dir.path().join("dest");
+        fs::create_dir(&destination)?;
+        fs::write(destination.join("sub"), "not a directory")?;
+
+        let copy = Copy {
+            source: source.display().to_string(),
+            destination: destination.display().to_string(),
+            ..Default::default()
+        };
+        let message = format!(
+            "{:#}",
+            execute_copy(&copy, &ExecuteOptions::default()).unwrap_err()
+        );
+        assert!(message.contains(&format!(
+            "Failed to copy {} to {}",
+            source.join("sub").join("a.txt").display(),
+            destination.join("sub").join("a.txt").display()
+        )));
+        assert!(message.contains(&format!(
+            "Failed to create directory {}",
+            destination.join("sub").display()
+        )));
+        Ok(())
+    }
+
+    #[test]
+    // 测试run_defaults应用到所有run命令，命令中设置的值优先
+    fn test_run_defaults() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.yml");
+        fs::write(
+            &config_path,
+            "run_defaults:\n  env:\n    TOOLCHAIN: stable\n    TARGET: x64\n  cwd: build\n  use_shell_fallback: false\ncommand:\n  - type: Run\n    command: make\n    env:\n      TARGET: arm64\n  - type: Group\n    commands:\n      - type: Run\n        command: make\n        use_shell_fallback: true\n    cwd: other\n",
+        )?;
+        let config =
+            parse_commands_from_yaml(&config_path.display().to_string(), true, None, None)?.0;
+        let Command::Run(run) = &config.command[0].command else {
+            unreachable!()
+        };
+        assert_eq!(run.env["TOOLCHAIN"], "stable");
+        assert_eq!(run.env["TARGET"], "arm64");
+        assert_eq!(run.cwd.as_deref(), Some("build"));
+        assert_eq!(run.use_shell_fallback, Some(false));
+        let Command::Group(group) = &config.command[1].command else {
+            unreachable!()
+        };
+        let Command::Run(run) = &group.commands[0] else {
+            unreachable!()
+        };
+        assert_eq!(run.env["TARGET"], "x64");
+        assert_eq!(run.cwd.as_deref(), Some("other"));
+        assert_eq!(run.use_shell_fallback, Some(true));
+
+        // 环境变量传给子进程
+        if cfg!(unix) {
+            let run = Run {
+                command: "sh -c \"echo [$TOOLCHAIN]\"".to_string(),
+                env: BTreeMap::from([("TOOLCHAIN".to_string(), "stable".to_string())]),
+                expect_stdout: Some(r"^\[stable\]".to_string()),
+                ..Default::default()
+            };
+            execute_run(&run, &ExecuteOptions::default())?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    // 测试按指定的编码解码命令的输出
+    fn test_decode_output() {
+        // GBK编码的"中文"
+        let gbk = [0xD6, 0xD0, 0xCE, 0xC4];
+        assert_eq!(decode_output(&gbk, Some(encoding_rs::GBK)), "中文");
+        assert_eq!(decode_output(&gbk, None), "\u{FFFD}".repeat(4));
+        assert_eq!(decode_output("中文".as_bytes(), None), "中文");
+
+        let run = Run {
+            command: "echo".to_string(),
+            output_encoding: Some("no-such-encoding".to_string()),
+            ..Default::default()
+        };
+        assert!(execute_run(&run, &ExecuteOptions::default()).is_err());
+    }
+
+    #[test]
+    // 测试用一句话描述命令
+    fn test_describe_command() {
+        let copy = Command::Copy(Copy {
+            source: "build/".to_string(),
+            destination: "dist/".to_string(),
+            gitignore_path: ".gitignore".to_string(),
+            use_gitignore: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            copy.describe(),
+            "copy files from build/ to dist/, honoring .gitignore"
+        );
+
+        let run = Command::Run(Run {
+            command: "cargo build".to_string(),
+            retries: 2,
+            ..Default::default()
+        });
+        assert_eq!(
+            run.describe(),
+            "run `cargo build`, retrying up to 2 time(s)"
+        );
+    }
+
+    #[test]
+    // 测试配置文件路径相关的内置变量
+    fn test_config_path_variables() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.yml");
+        fs::write(
+            &config_path,
+            "command:\n  - type: Run\n    command: \"echo ${__CONFIG_DIR__}/${__CONFIG_FILE__}\"\n",
+        )?;
+
+        let config =
+            parse_commands_from_yaml(&config_path.display().to_string(), true, None, None)?.0;
+        match &config.command[0].command {
+            Command::Run(run) => assert_eq!(
+                run.command,
+                format!(
+                    "echo {}/config.yml",
+                    std::path::absolute(dir.path())?.display()
+                )
+            ),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    // 测试按目标系统生效的定义项
+    fn test_define_items_os() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.yml");
+        fs::write(
+            &config_path,
+            format!(
+                "define_items:\n  - key: ext\n    value: \".exe\"\n    os: {os}\n  - key: ext\n    value: \"\"\n  - key: name\n    value: app\n  - key: name\n    value: other\n    os: not-{os}\ncommand:\n  - type: Run\n    command: \"echo ${{name}}${{ext}}\"\n",
+                os = std::env::consts::OS
+            ),
+        )?;
+
+        let config =
+            parse_commands_from_yaml(&config_path.display().to_string(), true, None, None)?.0;
+        match &config.command[0].command {
+            Command::Run(run) => assert_eq!(run.command, "echo app.exe"),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    // 测试以命令的输出作为定义项的值
+    fn test_define_from_command() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.yml");
+        fs::write(
+            &config_path,
+            "define_items:\n  - key: sha\n    from_command: echo \" abc123 \"\ncommand:\n  - type: Run\n    command: \"echo ${sha}\"\n",
+        )?;
+        let config =
+            parse_commands_from_yaml(&config_path.display().to_string(), true, None, None)?.0;
+        match &config.command[0].command {
+            Command::Run(run) => assert_eq!(run.command, "echo abc123"),
+            _ => unreachable!(),
+        }
+
+        fs::write(
+            &config_path,
+            "define_items:\n  - key: sha\n    from_command: \"false\"\n",
+        )?;
+        assert!(
+            parse_commands_from_yaml(&config_path.display().to_string(), true, None, None).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    // 测试对每处匹配进行内置转换
+    fn test_replace_transform() -> Result<()> {
+        let regex = Regex::new(r"build=\d+")?;
+        assert_eq!(
+            replace_transformed(&regex, "a build=009 b", "$0", Transform::Increment)?,
+            "a build=010 b"
+        );
+        let regex = Regex::new(r"name: (\w+)")?;
+        assert_eq!(
+            replace_transformed(&regex, "name: demo", "NAME: $1", Transform::Upper)?,
+            "NAME: DEMO"
+        );
+        assert_eq!(
+            replace_transformed(&regex, "name: demo", "$1", Transform::Base64)?,
+            "ZGVtbw=="
+        );
+        assert!(replace_transformed(&regex, "name: demo", "$1", Transform::Increment).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // 测试预演模式下replace不修改文件，其他命令跳过
+    fn test_dry_run() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::write(root.join("a.txt"), "version 1\n")?;
+
+        let options = ExecuteOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let replace = Command::Replace(Replace {
+            source: root.join("a.txt").display().to_string(),
+            regex: "1".to_string(),
+            replacement: "2".to_string(),
+            ..Default::default()
+        });
+        execute_command(&replace, &options)?;
+        assert_eq!(fs::read_to_string(root.join("a.txt"))?, "version 1\n");
+
+        let run = Command::Run(Run {
+            command: "false".to_string(),
+            ..Default::default()
+        });
+        execute_command(&run, &options)?;
+        Ok(())
+    }
+
+    #[test]
+    // 测试重命名目录树中匹配的文件和目录
+    fn test_replace_rename_paths() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("__APPNAME__/src/__APPNAME__"))?;
+        fs::write(root.join("__APPNAME__/src/__APPNAME__/__APPNAME__.rs"), "")?;
+
+        let replace = Replace {
+            source: root.display().to_string(),
+            regex: "__APPNAME__".to_string(),
+            replacement: "demo".to_string(),
+            rename_paths: true,
+            ..Default::default()
+        };
+        execute_replace(&replace, &ExecuteOptions::default())?;
+        assert!(root.join("demo/src/demo/demo.rs").is_file());
+        assert!(!root.join("__APPNAME__").exists());
+
+        let stdin = Replace {
+            source: STDIO_SOURCE.to_string(),
+            ..replace
+        };
+        let error = execute_replace(&stdin, &ExecuteOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("can not be used with stdin"));
+        Ok(())
     }
 }